@@ -96,6 +96,17 @@ impl Camera {
     let axis = axis.mul_v(&Vector3::new(1.0, 0.0, 0.0));
     self.rotate(&axis, r);
   }
+
+  /// The direction the camera is looking, computed the same way as the server does for players.
+  pub fn forward(&self) -> Vector3<f32> {
+    let y_axis = Vector3::new(0.0, 1.0, 0.0);
+    let lateral = Matrix3::from_axis_angle(&y_axis, cgmath::rad(self.lateral_rotation));
+    let right = lateral.mul_v(&Vector3::new(1.0, 0.0, 0.0));
+    let transform =
+      Matrix3::from_axis_angle(&right, cgmath::rad(self.vertical_rotation))
+      .mul_m(&lateral);
+    transform.mul_v(&Vector3::new(0.0, 0.0, -1.0))
+  }
 }
 
 /// Set a shader's projection matrix to match that of a camera.
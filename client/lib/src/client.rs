@@ -1,6 +1,6 @@
 //! Main Playform client state code.
 
-use cgmath::Point3;
+use cgmath::{Point3, Vector3};
 use num::iter::range_inclusive;
 use std::sync::Mutex;
 
@@ -45,10 +45,21 @@ pub struct T {
   pub voxels: Mutex<voxel::tree::T>,
   /// The number of terrain requests that are outstanding,
   pub outstanding_terrain_requests: Mutex<u32>,
+  /// The brush the player edits the terrain with.
+  pub brush: Mutex<protocol::Brush>,
+  /// The biggest brush the server allows, along any axis from its center.
+  pub max_brush_extent: f32,
 }
 
 #[allow(missing_docs)]
-pub fn new(client_id: protocol::ClientId, player_id: entity_id::T, position: Point3<f32>) -> T {
+pub fn new(
+  client_id: protocol::ClientId,
+  player_id: entity_id::T,
+  position: Point3<f32>,
+  max_brush_extent: f32,
+) -> T {
+  let brush_extent = max_brush_extent.min(8.0);
+
   let mut load_distance = load_distance(terrain_buffers::POLYGON_BUDGET as i32);
 
   if load_distance > MAX_LOAD_DISTANCE {
@@ -77,6 +88,13 @@ pub fn new(client_id: protocol::ClientId, player_id: entity_id::T, position: Poi
     block_voxels_loaded: Mutex::new(block_position::with_lod::map::new()),
     voxels: Mutex::new(voxel::tree::new()),
     outstanding_terrain_requests: Mutex::new(0),
+    brush: Mutex::new(protocol::Brush {
+      shape: protocol::BrushShape::Sphere,
+      mode: protocol::BrushMode::Remove,
+      extents: Vector3::new(brush_extent, brush_extent, brush_extent),
+      material: voxel::Material::Stone,
+    }),
+    max_brush_extent: max_brush_extent,
   }
 }
 
@@ -3,12 +3,94 @@
 use cgmath::Point2;
 
 use common::color::Color4;
+use common::protocol;
+use common::voxel;
 
 use vertex::{ColoredVertex};
 use view;
 
+/// The brush shapes, in the order they're shown in the brush indicator.
+pub const BRUSH_SHAPES: [protocol::BrushShape; 4] = [
+  protocol::BrushShape::Sphere,
+  protocol::BrushShape::Cube,
+  protocol::BrushShape::Cylinder,
+  protocol::BrushShape::Cone,
+];
+/// The brush modes, in the order they're shown in the brush indicator.
+pub const BRUSH_MODES: [protocol::BrushMode; 4] = [
+  protocol::BrushMode::Add,
+  protocol::BrushMode::Remove,
+  protocol::BrushMode::ReplaceMaterial,
+  protocol::BrushMode::Smooth,
+];
+
+/// The number of vertices in the brush indicator.
+pub const BRUSH_VERTICES: usize = 6 * (BRUSH_SHAPES.len() + BRUSH_MODES.len() + 3);
+
+fn material_color(material: voxel::Material) -> Color4<f32> {
+  let (r, g, b) =
+    match material {
+      voxel::Material::Empty => (0.0, 0.0, 0.0),
+      voxel::Material::Terrain => (0.2, 0.6, 0.1),
+      voxel::Material::Bark => (0.4, 0.25, 0.1),
+      voxel::Material::Leaves => (0.1, 0.4, 0.1),
+      voxel::Material::Stone => (0.5, 0.5, 0.5),
+      voxel::Material::Dirt => (0.45, 0.3, 0.15),
+      voxel::Material::Sand => (0.9, 0.8, 0.5),
+      voxel::Material::Snow => (1.0, 1.0, 1.0),
+      voxel::Material::Ore => (0.7, 0.4, 0.3),
+    };
+  Color4::of_rgba(r, g, b, 1.0)
+}
+
+/// The brush indicator, in the bottom-left corner: a row of shapes, a row of modes
+/// (add, remove, replace material, smooth), the material, and a bar for the size.
+/// The size bar is full at `max_extent`.
+fn brush_indicator(brush: &protocol::Brush, max_extent: f32) -> Vec<ColoredVertex> {
+  let mut vertices = Vec::with_capacity(BRUSH_VERTICES);
+  let square = |x: f32, y: f32, w: f32, h: f32, color| {
+    ColoredVertex::square(Point2 { x: x, y: y }, Point2 { x: x + w, y: y + h }, color)
+  };
+  let faded = |mut color: Color4<f32>, selected| {
+    if !selected {
+      color.a = 0.25;
+    }
+    color
+  };
+
+  for (i, &shape) in BRUSH_SHAPES.iter().enumerate() {
+    let color = faded(Color4::of_rgba(1.0, 1.0, 1.0, 1.0), shape == brush.shape);
+    vertices.extend(square(-0.95 + 0.1 * i as f32, -0.95, 0.08, 0.08, color).iter().cloned());
+  }
+  vertices.extend(square(-0.5, -0.95, 0.08, 0.08, material_color(brush.material)).iter().cloned());
+
+  for (i, &mode) in BRUSH_MODES.iter().enumerate() {
+    let color =
+      match mode {
+        protocol::BrushMode::Add => Color4::of_rgba(0.2, 0.8, 0.2, 1.0),
+        protocol::BrushMode::Remove => Color4::of_rgba(0.8, 0.2, 0.2, 1.0),
+        protocol::BrushMode::ReplaceMaterial => Color4::of_rgba(0.2, 0.4, 0.9, 1.0),
+        protocol::BrushMode::Smooth => Color4::of_rgba(0.9, 0.8, 0.2, 1.0),
+      };
+    vertices.extend(square(-0.95 + 0.1 * i as f32, -0.85, 0.08, 0.08, faded(color, mode == brush.mode)).iter().cloned());
+  }
+
+  let size = brush.extents.x.max(brush.extents.y).max(brush.extents.z) / max_extent;
+  vertices.extend(square(-0.95, -0.75, 0.38, 0.03, Color4::of_rgba(0.0, 0.0, 0.0, 0.5)).iter().cloned());
+  vertices.extend(square(-0.95, -0.75, 0.38 * size.min(1.0), 0.03, Color4::of_rgba(1.0, 1.0, 1.0, 0.8)).iter().cloned());
+
+  vertices
+}
+
+/// Update the brush indicator in the HUD.
+pub fn show_brush<'a, 'b:'a>(view: &'a mut view::T<'b>, brush: &protocol::Brush, max_extent: f32) {
+  let vertices = brush_indicator(brush, max_extent);
+  view.brush_triangles.buffer.byte_buffer.bind(&mut view.gl);
+  view.brush_triangles.buffer.update(&mut view.gl, 0, &vertices);
+}
+
 /// Add HUD data into `view`.
-pub fn make_hud<'a, 'b:'a>(view: &'a mut view::T<'b>) {
+pub fn make_hud<'a, 'b:'a>(view: &'a mut view::T<'b>, brush: &protocol::Brush, max_brush_extent: f32) {
   let cursor_color = Color4::of_rgba(0.0, 0.0, 0.0, 0.75);
 
   let triangles: Vec<_> =
@@ -20,4 +102,19 @@ pub fn make_hud<'a, 'b:'a>(view: &'a mut view::T<'b>) {
 
   view.hud_triangles.bind(&mut view.gl);
   view.hud_triangles.push(&mut view.gl, triangles.as_ref());
+
+  // Tint the whole screen red while dead.
+  let death_color = Color4::of_rgba(0.5, 0.0, 0.0, 0.6);
+  let triangles =
+    ColoredVertex::square(
+      Point2 { x: -1.0, y: -1.0 },
+      Point2 { x:  1.0, y:  1.0 },
+      death_color
+    );
+  view.death_triangles.bind(&mut view.gl);
+  view.death_triangles.push(&mut view.gl, &triangles);
+
+  let triangles = brush_indicator(brush, max_brush_extent);
+  view.brush_triangles.bind(&mut view.gl);
+  view.brush_triangles.push(&mut view.gl, &triangles);
 }
@@ -71,6 +71,24 @@ impl<'a> PlayerBuffers<'a> {
     }
   }
 
+  /// Remove a player from VRAM, if it's there.
+  pub fn swap_remove(&mut self, gl: &mut GLContext, id: entity_id::T) {
+    let idx =
+      match self.id_to_index.remove(&id) {
+        None => return,
+        Some(idx) => idx,
+      };
+    let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
+    self.index_to_id.swap_remove(idx);
+
+    if id != swapped_id {
+      self.id_to_index.insert(swapped_id, idx);
+    }
+
+    self.triangles.buffer.byte_buffer.bind(gl);
+    self.triangles.buffer.swap_remove(gl, idx * VERTICES_PER_PLAYER, VERTICES_PER_PLAYER);
+  }
+
   /// Draw all the mobs.
   /// N.B. This does not bind any shaders.
   pub fn draw(&self, gl: &mut GLContext) {
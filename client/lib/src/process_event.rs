@@ -1,6 +1,6 @@
 //! SDL input event processing code.
 
-use cgmath::{Vector2, Vector3};
+use cgmath::{Vector, Vector2, Vector3};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::mouse::Mouse;
@@ -9,10 +9,33 @@ use stopwatch;
 
 use common::entity_id;
 use common::protocol;
+use common::voxel;
 
 use client;
+use hud;
 use view;
 
+/// How much one step of the mouse wheel scales the brush by.
+const BRUSH_SCALE_STEP: f32 = 1.25;
+
+/// The materials players can cycle through for their brush.
+const BRUSH_MATERIALS: [voxel::Material; 8] = [
+  voxel::Material::Terrain,
+  voxel::Material::Stone,
+  voxel::Material::Dirt,
+  voxel::Material::Sand,
+  voxel::Material::Snow,
+  voxel::Material::Bark,
+  voxel::Material::Leaves,
+  voxel::Material::Ore,
+];
+
+/// The element after `x` in `xs`, wrapping around.
+fn next<X: Copy + PartialEq>(xs: &[X], x: X) -> X {
+  let i = xs.iter().position(|&y| y == x).map_or(0, |i| i + 1);
+  xs[i % xs.len()]
+}
+
 #[allow(missing_docs)]
 pub fn process_event<UpdateServer>(
   update_server: &mut UpdateServer,
@@ -40,7 +63,18 @@ pub fn process_event<UpdateServer>(
       mouse_move(client.player_id, update_server, view, xrel, yrel);
     },
     Event::MouseButtonDown{mouse_btn, ..} => {
-      mouse_press(client.player_id, update_server, mouse_btn);
+      mouse_press(update_server, view, client, mouse_btn);
+    },
+    Event::MouseWheel{y, ..} => {
+      let max_extent = client.max_brush_extent;
+      change_brush(view, client, |brush| {
+        // Keep the brush's proportions, within the server's limit and no smaller than a block.
+        let extents = brush.extents;
+        let largest = extents.x.max(extents.y).max(extents.z);
+        let smallest = extents.x.min(extents.y).min(extents.z);
+        let scale = BRUSH_SCALE_STEP.powi(y).min(max_extent / largest).max(1.0 / smallest);
+        brush.extents = extents.mul_s(scale);
+      });
     },
     _ => {},
   }
@@ -96,27 +130,58 @@ fn key_press<UpdateServer>(
           Some(_) => *load_position = None,
         }
       },
+      Keycode::B => {
+        change_brush(view, client, |brush| brush.shape = next(&hud::BRUSH_SHAPES, brush.shape));
+      },
+      Keycode::M => {
+        change_brush(view, client, |brush| brush.mode = next(&hud::BRUSH_MODES, brush.mode));
+      },
+      Keycode::N => {
+        change_brush(view, client, |brush| brush.material = next(&BRUSH_MATERIALS, brush.material));
+      },
+      Keycode::Z => {
+        update_server(protocol::ClientToServer::Undo(client.player_id));
+      },
+      Keycode::Y => {
+        update_server(protocol::ClientToServer::Redo(client.player_id));
+      },
       _ => {},
     }
   })
 }
 
+fn change_brush<Change>(
+  view: &mut view::T,
+  client: &client::T,
+  change: Change,
+) where Change: FnOnce(&mut protocol::Brush)
+{
+  let mut brush = client.brush.lock().unwrap();
+  change(&mut brush);
+  info!("Brush: {:?}", *brush);
+  hud::show_brush(view, &brush, client.max_brush_extent);
+}
+
 fn mouse_press<UpdateServer>(
-  player_id: entity_id::T,
   update_server: &mut UpdateServer,
+  view: &view::T,
+  client: &client::T,
   mouse_btn: Mouse,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
   stopwatch::time("event.mouse_press", || {
+    // The server checks edits against where it thinks we're looking, which can lag behind the camera.
+    let aim = view.camera.forward();
     match mouse_btn {
       Mouse::Left => {
         update_server(
-          protocol::ClientToServer::Add(player_id)
+          protocol::ClientToServer::PlantTree(client.player_id, aim)
         );
       },
       Mouse::Right => {
+        let brush = *client.brush.lock().unwrap();
         update_server(
-          protocol::ClientToServer::Remove(player_id)
+          protocol::ClientToServer::Brush(client.player_id, aim, brush)
         );
       },
       _ => {},
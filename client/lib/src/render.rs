@@ -24,10 +24,18 @@ pub fn render(
   rndr.mob_buffers.draw(&mut rndr.gl);
   rndr.player_buffers.draw(&mut rndr.gl);
 
+  if rndr.dead {
+    rndr.shaders.hud_color_shader.shader.use_shader(&mut rndr.gl);
+    rndr.death_triangles.bind(&mut rndr.gl);
+    rndr.death_triangles.draw(&mut rndr.gl);
+  }
+
   if rndr.show_hud {
     rndr.shaders.hud_color_shader.shader.use_shader(&mut rndr.gl);
     rndr.hud_triangles.bind(&mut rndr.gl);
     rndr.hud_triangles.draw(&mut rndr.gl);
+    rndr.brush_triangles.bind(&mut rndr.gl);
+    rndr.brush_triangles.draw(&mut rndr.gl);
 
     // draw hud textures
     rndr.shaders.hud_texture_shader.shader.use_shader(&mut rndr.gl);
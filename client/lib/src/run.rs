@@ -21,7 +21,11 @@ pub fn run(listen_url: &str, server_url: &str) {
 
   let server = server::new(&server_url, &listen_url);
 
-  let client = connect_client(&listen_url, &server);
+  let client =
+    match connect_client(&listen_url, &server) {
+      None => return,
+      Some(client) => client,
+    };
   let client = &client;
 
   {
@@ -70,7 +74,8 @@ pub fn run(listen_url: &str, server_url: &str) {
   }
 }
 
-fn connect_client(listen_url: &str, server: &server::T) -> client::T {
+/// Join the server, or return None if it shuts down first.
+fn connect_client(listen_url: &str, server: &server::T) -> Option<client::T> {
   // TODO: Consider using RPCs to solidify the request-response patterns.
   server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
   loop {
@@ -80,8 +85,12 @@ fn connect_client(listen_url: &str, server: &server::T) -> client::T {
         let client_id = client_id;
         loop {
           match server.listen.wait() {
-            protocol::ServerToClient::PlayerAdded(player_id, position) => {
-              return client::new(client_id, player_id, position);
+            protocol::ServerToClient::PlayerAdded(player_id, position, max_brush_extent) => {
+              return Some(client::new(client_id, player_id, position, max_brush_extent));
+            },
+            protocol::ServerToClient::ShuttingDown { reason } => {
+              println!("The server is shutting down: {}", reason);
+              return None;
             },
             msg => {
               // Ignore other messages in the meantime.
@@ -36,13 +36,17 @@ pub fn apply_server_update<UpdateView, UpdateServer, EnqueueBlockUpdates>(
       protocol::ServerToClient::LeaseId(_) => {
         warn!("Client ID has already been leased.");
       },
+      protocol::ServerToClient::ShuttingDown { reason } => {
+        println!("The server is shutting down: {}", reason);
+        update_view(ClientToView::Quit);
+      },
       protocol::ServerToClient::Ping => {
         update_server(protocol::ClientToServer::Ping(client.id));
       },
-      protocol::ServerToClient::PlayerAdded(id, _) => {
+      protocol::ServerToClient::PlayerAdded(id, _, _) => {
         warn!("Unexpected PlayerAdded event: {:?}.", id);
       },
-      protocol::ServerToClient::UpdatePlayer(player_id, bounds) => {
+      protocol::ServerToClient::UpdatePlayer(_, player_id, bounds) => {
         let mesh = to_triangles(&bounds, &Color4::of_rgba(0.0, 0.0, 1.0, 1.0));
         update_view(ClientToView::UpdatePlayer(player_id, mesh));
 
@@ -57,11 +61,37 @@ pub fn apply_server_update<UpdateView, UpdateServer, EnqueueBlockUpdates>(
         *client.player_position.lock().unwrap() = position;
         update_view(ClientToView::MoveCamera(position));
       },
-      protocol::ServerToClient::UpdateMob(id, bounds) => {
+      protocol::ServerToClient::UpdateHealth(player_id, health) => {
+        if player_id == client.player_id {
+          info!("Health: {}", health);
+        }
+      },
+      protocol::ServerToClient::PlayerDied(player_id) => {
+        if player_id == client.player_id {
+          info!("You died.");
+          update_view(ClientToView::SetDead(true));
+        }
+      },
+      protocol::ServerToClient::PlayerRespawned(player_id, position) => {
+        if player_id == client.player_id {
+          // Forget where we thought we were.
+          *client.player_position.lock().unwrap() = position;
+          *client.load_position.lock().unwrap() = None;
+          update_view(ClientToView::MoveCamera(position));
+          update_view(ClientToView::SetDead(false));
+        }
+      },
+      protocol::ServerToClient::RemovePlayer(player_id) => {
+        update_view(ClientToView::RemovePlayer(player_id));
+      },
+      protocol::ServerToClient::UpdateMob(_, id, bounds) => {
         let mesh = to_triangles(&bounds, &Color4::of_rgba(1.0, 0.0, 0.0, 1.0));
         update_view(ClientToView::UpdateMob(id, mesh));
       },
-      protocol::ServerToClient::UpdateSun(fraction) => {
+      protocol::ServerToClient::RemoveMob(id) => {
+        update_view(ClientToView::RemoveMob(id));
+      },
+      protocol::ServerToClient::UpdateSun(_, fraction) => {
         // Convert to radians.
         let angle = fraction * 2.0 * PI;
         let (s, c) = angle.sin_cos();
@@ -10,6 +10,9 @@ mod bark;
 mod dirt;
 mod grass;
 mod leaves;
+mod ore;
+mod sand;
+mod snow;
 mod stone;
 
 use camera;
@@ -0,0 +1,38 @@
+pub fn ore() -> String {
+  struct Wave {
+    freq: f32,
+    amp: f32,
+  }
+
+  let waves = [
+    Wave { freq:  2.0, amp: 1.0 },
+    Wave { freq: 16.0, amp: 0.6 },
+  ];
+
+  let mut contents = String::new();
+  for wave in &waves {
+    contents.push_str(format!(r#"
+    {{
+      float freq = {};
+      float amp = {};
+
+      float dnoise = cnoise(freq * world_position);
+      // sharpen
+      dnoise = sign(dnoise) * pow(abs(dnoise), 0.2);
+      noise += dnoise * amp;
+      total_amp += amp;
+    }}
+    "#, wave.freq, wave.amp).as_str());
+  }
+
+  format!(r#"
+    float total_amp = 0.0;
+    float noise = 0.0;
+    {}
+    noise /= total_amp;
+    noise = (noise + 1) / 2;
+
+    // Flecks of rusty ore in a dark stone matrix.
+    return mix(vec3(0.25, 0.2, 0.2), vec3(0.7, 0.35, 0.15), noise);
+  "#, contents)
+}
@@ -86,6 +86,18 @@ impl<'a> TerrainShader<'a> {
           {}
         }}
 
+        vec3 sand() {{
+          {}
+        }}
+
+        vec3 snow() {{
+          {}
+        }}
+
+        vec3 ore() {{
+          {}
+        }}
+
         void main() {{
           vec4 base_color;
 
@@ -102,6 +114,14 @@ impl<'a> TerrainShader<'a> {
             base_color = vec4(leaves(), 1);
           }} else if (material == 4) {{
             base_color = vec4(stone(), 1);
+          }} else if (material == 5) {{
+            base_color = vec4(dirt(), 1);
+          }} else if (material == 6) {{
+            base_color = vec4(sand(), 1);
+          }} else if (material == 7) {{
+            base_color = vec4(snow(), 1);
+          }} else if (material == 8) {{
+            base_color = vec4(ore(), 1);
           }} else {{
             base_color = vec4(0.5, 0, 0.5, 0.5);
           }}
@@ -125,6 +145,9 @@ impl<'a> TerrainShader<'a> {
         ::shaders::bark::bark(),
         ::shaders::leaves::leaves(),
         ::shaders::stone::stone(),
+        ::shaders::sand::sand(),
+        ::shaders::snow::snow(),
+        ::shaders::ore::ore(),
       )),
     );
     TerrainShader {
@@ -12,6 +12,7 @@ use common::id_allocator;
 use camera::Camera;
 use gl;
 use gl::types::*;
+use hud;
 use mob_buffers::MobBuffers;
 use player_buffers::PlayerBuffers;
 use shaders::Shaders;
@@ -35,6 +36,10 @@ pub struct T<'a> {
   pub player_buffers: PlayerBuffers<'a>,
   /// Hud triangles for non-text.
   pub hud_triangles: GLArray<'a, ColoredVertex>,
+  /// Drawn over everything while the player is dead.
+  pub death_triangles: GLArray<'a, ColoredVertex>,
+  /// Shows the current brush; see `hud::show_brush`.
+  pub brush_triangles: GLArray<'a, ColoredVertex>,
 
   /// A texture unit for misc use.
   pub misc_texture_unit: TextureUnit,
@@ -44,6 +49,10 @@ pub struct T<'a> {
 
   #[allow(missing_docs)]
   pub show_hud: bool,
+  /// Whether the player is dead.
+  pub dead: bool,
+  /// Whether the view should close, e.g. because the server shut down.
+  pub quit: bool,
 }
 
 impl<'a> T<'a> {
@@ -77,6 +86,34 @@ impl<'a> T<'a> {
       )
     };
 
+    let buffer = GLBuffer::new(&mut gl, 2 * VERTICES_PER_TRIANGLE);
+    let death_triangles = {
+      GLArray::new(
+        &mut gl,
+        &shaders.hud_color_shader.shader,
+        &[
+          VertexAttribData { name: "position", size: 3, unit: GLType::Float },
+          VertexAttribData { name: "in_color", size: 4, unit: GLType::Float },
+        ],
+        DrawMode::Triangles,
+        buffer,
+      )
+    };
+
+    let buffer = GLBuffer::new(&mut gl, hud::BRUSH_VERTICES);
+    let brush_triangles = {
+      GLArray::new(
+        &mut gl,
+        &shaders.hud_color_shader.shader,
+        &[
+          VertexAttribData { name: "position", size: 3, unit: GLType::Float },
+          VertexAttribData { name: "in_color", size: 4, unit: GLType::Float },
+        ],
+        DrawMode::Triangles,
+        buffer,
+      )
+    };
+
     let misc_texture_unit = texture_unit_alloc.allocate();
 
     unsafe {
@@ -114,6 +151,8 @@ impl<'a> T<'a> {
       mob_buffers: mob_buffers,
       player_buffers: player_buffers,
       hud_triangles: hud_triangles,
+      death_triangles: death_triangles,
+      brush_triangles: brush_triangles,
 
       misc_texture_unit: misc_texture_unit,
 
@@ -129,6 +168,8 @@ impl<'a> T<'a> {
       },
 
       show_hud: true,
+      dead: false,
+      quit: false,
     }
   }
 }
@@ -85,7 +85,7 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
 
   sdl.mouse().set_relative_mouse_mode(true);
 
-  make_hud(&mut view);
+  make_hud(&mut view, &client.brush.lock().unwrap(), client.max_brush_extent);
 
   let render_interval = {
     let nanoseconds_per_second = 1000000000;
@@ -144,6 +144,10 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
           }
         });
 
+        if view.quit {
+          return ViewIteration::Quit
+        }
+
         let renders = render_timer.update(time::precise_time_ns());
         if renders > 0 {
           stopwatch::time("render", || {
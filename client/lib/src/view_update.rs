@@ -23,8 +23,12 @@ pub enum ClientToView {
 
   /// Update a player mesh.
   UpdatePlayer(entity_id::T, [ColoredVertex; VERTICES_PER_PLAYER]),
+  /// Remove a player mesh.
+  RemovePlayer(entity_id::T),
   /// Update a mob mesh.
   UpdateMob(entity_id::T, [ColoredVertex; VERTICES_PER_MOB]),
+  /// Remove a mob mesh.
+  RemoveMob(entity_id::T),
 
   /// Update the sun.
   SetSun(light::Sun),
@@ -32,6 +36,10 @@ pub enum ClientToView {
   SetAmbientLight(Color3<f32>),
   /// Update the GL clear color.
   SetClearColor(Color3<f32>),
+  /// Show or hide the death screen.
+  SetDead(bool),
+  /// Close the view.
+  Quit,
 
   /// Add a terrain block to the view.
   AddBlock(block_position::T, terrain_mesh::T, lod::T),
@@ -52,9 +60,15 @@ pub fn apply_client_to_view(view: &mut view::T, up: ClientToView) {
     ClientToView::UpdateMob(id, triangles) => {
       view.mob_buffers.insert(&mut view.gl, id, &triangles);
     },
+    ClientToView::RemoveMob(id) => {
+      view.mob_buffers.swap_remove(&mut view.gl, id);
+    },
     ClientToView::UpdatePlayer(id, triangles) => {
       view.player_buffers.insert(&mut view.gl, id, &triangles);
     },
+    ClientToView::RemovePlayer(id) => {
+      view.player_buffers.swap_remove(&mut view.gl, id);
+    },
     ClientToView::SetSun(sun) => {
       set_sun(
         &mut view.shaders.terrain_shader.shader,
@@ -72,6 +86,12 @@ pub fn apply_client_to_view(view: &mut view::T, up: ClientToView) {
     ClientToView::SetClearColor(color) => {
       view.gl.set_background_color(color.r, color.g, color.b, 1.0);
     },
+    ClientToView::SetDead(dead) => {
+      view.dead = dead;
+    },
+    ClientToView::Quit => {
+      view.quit = true;
+    },
     ClientToView::AddBlock(_, block, _) => {
       stopwatch::time("add_block", || {
         view.terrain_buffers.push(
@@ -15,6 +15,11 @@ impl IntervalTimer {
     }
   }
 
+  /// The time the current interval ends.
+  pub fn next(&self) -> u64 {
+    self.next
+  }
+
   #[inline]
   /// Returns the number of intervals that have elapsed since last `update`.
   pub fn update(&mut self, current: u64) -> u64 {
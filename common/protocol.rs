@@ -26,6 +26,10 @@ impl Add<u32> for ClientId {
   }
 }
 
+/// The number of a server update. The server numbers its updates consecutively from 0, and
+/// tags the state it sends with the update it's from.
+pub type Tick = u64;
+
 #[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 /// Messages the client sends to the server.
 pub enum ClientToServer {
@@ -45,10 +49,52 @@ pub enum ClientToServer {
   StopJump(entity_id::T),
   /// Ask the server to send a block of terrain.
   RequestVoxels(ClientId, Vec<voxel::bounds::T>),
-  /// Brush-remove where the player's looking.
-  Add(entity_id::T),
-  /// Brush-add at where the player's looking.
-  Remove(entity_id::T),
+  /// Plant a tree where the player's looking, along with the direction they aimed in.
+  PlantTree(entity_id::T, Vector3<f32>),
+  /// Apply a brush where the player's looking, along with the direction they aimed in.
+  Brush(entity_id::T, Vector3<f32>, Brush),
+  /// Undo the player's last edit.
+  Undo(entity_id::T),
+  /// Redo the player's last undone edit.
+  Redo(entity_id::T),
+}
+
+/// The shape of a brush, centered on where it's applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, RustcEncodable, RustcDecodable)]
+#[allow(missing_docs)]
+pub enum BrushShape {
+  Sphere,
+  Cube,
+  Cylinder,
+  /// Pointing up.
+  Cone,
+}
+
+/// What a brush does to the terrain inside its shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, RustcEncodable, RustcDecodable)]
+pub enum BrushMode {
+  /// Fill it with the brush's material.
+  Add,
+  /// Clear it out.
+  Remove,
+  /// Change what the terrain is made of to the brush's material, without changing its shape.
+  ReplaceMaterial,
+  /// Even out bumps and holes in the terrain.
+  Smooth,
+}
+
+/// A description of an edit to the terrain.
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Brush {
+  #[allow(missing_docs)]
+  pub shape: BrushShape,
+  #[allow(missing_docs)]
+  pub mode: BrushMode,
+  /// Half the brush's size along each axis, e.g. a sphere's radii.
+  /// Cylinders and cones stand along y.
+  pub extents: Vector3<f32>,
+  /// The material added or replaced with. Ignored when removing or smoothing.
+  pub material: voxel::Material,
 }
 
 /// Why a block is being sent to a client.
@@ -68,17 +114,34 @@ pub enum ServerToClient {
   /// Ping
   Ping,
 
-  /// Complete an AddPlayer request.
-  PlayerAdded(entity_id::T, Point3<f32>),
+  /// Complete an AddPlayer request, with the player's position and the biggest brush extent
+  /// (along any axis from its center) the server allows.
+  PlayerAdded(entity_id::T, Point3<f32>, f32),
   /// Update a player's position.
-  UpdatePlayer(entity_id::T, Aabb3<f32>),
+  UpdatePlayer(Tick, entity_id::T, Aabb3<f32>),
+  /// A player's health changed.
+  UpdateHealth(entity_id::T, u32),
+  /// A player died, and will be respawned shortly.
+  PlayerDied(entity_id::T),
+  /// A dead player was put back into the world, centered at a position.
+  PlayerRespawned(entity_id::T, Point3<f32>),
+  /// A player has been removed from the world.
+  RemovePlayer(entity_id::T),
 
   /// Update the client's view of a mob with a given mesh.
-  UpdateMob(entity_id::T, Aabb3<f32>),
+  UpdateMob(Tick, entity_id::T, Aabb3<f32>),
+  /// A mob has been removed from the world.
+  RemoveMob(entity_id::T),
 
   /// The sun as a [0, 1) portion of its cycle.
-  UpdateSun(f32),
+  UpdateSun(Tick, f32),
 
   /// Provide a block of terrain to a client.
   Voxels(Vec<(voxel::bounds::T, voxel::T)>, VoxelReason),
+
+  /// The server is going away; nothing more will be sent.
+  ShuttingDown {
+    /// Why, for showing to players.
+    reason: String,
+  },
 }
@@ -72,6 +72,17 @@ impl ReceiveSocket {
     msg
   }
 
+  /// Block until a message can be fetched from this socket, or until the socket's timeout passes,
+  /// in which case this returns `Ok(None)`.
+  pub fn read_or_timeout(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+    let mut msg = Vec::new();
+    match self.socket.read_to_end(&mut msg) {
+      Ok(_) => Ok(Some(msg)),
+      Err(ref err) if err.kind() == std::io::ErrorKind::TimedOut => Ok(None),
+      Err(err) => Err(err),
+    }
+  }
+
   /// Try to read a message from this socket.
   pub fn try_read(&mut self) -> Option<Vec<u8>> {
     let mut msg = Vec::new();
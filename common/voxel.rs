@@ -16,10 +16,15 @@ pub type T = voxel_data::impls::surface_vertex::T<Material>;
 /// Terrain materials
 pub enum Material {
   Empty = 0,
+  /// Topsoil; rendered as a blend of grass and dirt.
   Terrain = 1,
   Bark = 2,
   Leaves = 3,
   Stone = 4,
+  Dirt = 5,
+  Sand = 6,
+  Snow = 7,
+  Ore = 8,
 }
 
 #[allow(missing_docs)]
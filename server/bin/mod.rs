@@ -8,7 +8,6 @@
 #![allow(mutex_atomic)]
 
 extern crate env_logger;
-extern crate nanomsg;
 #[macro_use]
 extern crate log;
 extern crate thread_scoped;
@@ -19,42 +18,86 @@ use std::borrow::Borrow;
 use std::env;
 use std::sync::Mutex;
 
+use server_lib::admin;
+use server_lib::config;
+
 fn main() {
   env_logger::init().unwrap();
 
   let mut args = env::args();
   args.next().unwrap();
   let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
+  let config =
+    match args.next() {
+      None => Default::default(),
+      Some(path) => {
+        match config::load(&path) {
+          Ok(config) => config,
+          Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+          },
+        }
+      },
+    };
   assert!(args.next().is_none());
 
   info!("Listening on {}.", listen_url);
 
   let quit_signal = Mutex::new(false);
+  let admin = admin::inbox();
 
-  let _quit_thread =
+  // The server stops itself when the console tells it to quit.
+  let _console_thread =
     unsafe {
-      let quit_signal = &quit_signal;
+      let admin = &admin;
       thread_scoped::scoped(move || {
-        wait_for_quit();
-        *quit_signal.lock().unwrap() = true;
-        // Close all sockets.
-        nanomsg::Socket::terminate();
+        run_console(admin);
       })
     };
 
-  server_lib::run(listen_url.borrow(), &quit_signal);
+  server_lib::run(listen_url.borrow(), &quit_signal, &admin, config);
 }
 
-fn wait_for_quit() {
+/// Run commands from stdin until told to quit, or until stdin closes.
+/// Running without a console (e.g. detached, or with stdin from /dev/null) leaves the server up.
+fn run_console(admin: &admin::Inbox) {
   loop {
     let mut line = String::new();
-    std::io::stdin().read_line(&mut line).unwrap();
+    match std::io::stdin().read_line(&mut line) {
+      Ok(0) => {
+        info!("End of input; the console is closed.");
+        return
+      },
+      Ok(_) => {},
+      Err(ref err) if err.kind() == std::io::ErrorKind::InvalidData => {
+        // The bad line has been consumed, so just skip it.
+        println!("Couldn't read that line: {}", err);
+        continue
+      },
+      Err(err) => {
+        error!("Couldn't read from the console, so it's closed: {}", err);
+        return
+      },
+    }
+
+    if line.trim().is_empty() {
+      continue
+    }
 
-    if line == "quit\n" {
-      println!("Quitting");
-      return
-    } else {
-      println!("Unrecognized command: {:?}", line);
+    match admin::parse(&line) {
+      Err(err) => println!("{}", err),
+      Ok(command) => {
+        let is_quit =
+          match command {
+            admin::Command::Quit(_) => true,
+            _ => false,
+          };
+        println!("{}", admin.run(command));
+        if is_quit {
+          return
+        }
+      },
     }
   }
 }
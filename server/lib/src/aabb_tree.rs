@@ -0,0 +1,447 @@
+//! A dynamic AABB tree for broad-phase collision detection.
+//!
+//! Nodes live in an arena and refer to each other by index. Objects that move get
+//! "fattened" bounds in the tree, so small movements don't have to touch the tree at all.
+
+use cgmath::{Aabb3, Point3};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// How far past its actual bounds a moving object's node extends.
+pub const FAT_MARGIN: f32 = 0.5;
+
+/// Whether two boxes overlap by more than just touching.
+pub fn overlap(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
+  true
+  && aabb1.min.x < aabb2.max.x
+  && aabb1.min.y < aabb2.max.y
+  && aabb1.min.z < aabb2.max.z
+  && aabb2.min.x < aabb1.max.x
+  && aabb2.min.y < aabb1.max.y
+  && aabb2.min.z < aabb1.max.z
+}
+
+fn contains(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
+  true
+  && aabb1.min.x <= aabb2.min.x
+  && aabb1.min.y <= aabb2.min.y
+  && aabb1.min.z <= aabb2.min.z
+  && aabb2.max.x <= aabb1.max.x
+  && aabb2.max.y <= aabb1.max.y
+  && aabb2.max.z <= aabb1.max.z
+}
+
+fn union(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> Aabb3<f32> {
+  Aabb3::new(
+    Point3::new(aabb1.min.x.min(aabb2.min.x), aabb1.min.y.min(aabb2.min.y), aabb1.min.z.min(aabb2.min.z)),
+    Point3::new(aabb1.max.x.max(aabb2.max.x), aabb1.max.y.max(aabb2.max.y), aabb1.max.z.max(aabb2.max.z)),
+  )
+}
+
+/// Half the surface area; used as the cost of a node.
+fn cost(bounds: &Aabb3<f32>) -> f32 {
+  let x = bounds.max.x - bounds.min.x;
+  let y = bounds.max.y - bounds.min.y;
+  let z = bounds.max.z - bounds.min.z;
+  x*y + y*z + z*x
+}
+
+/// Grow `bounds` by the margin, and further in the direction it's moving.
+fn fatten(bounds: &Aabb3<f32>, old_bounds: &Aabb3<f32>) -> Aabb3<f32> {
+  let mut fat = *bounds;
+  macro_rules! fatten_axis(($d:ident) => {{
+    let displacement = bounds.min.$d - old_bounds.min.$d;
+    fat.min.$d = fat.min.$d - FAT_MARGIN + displacement.min(0.0);
+    fat.max.$d = fat.max.$d + FAT_MARGIN + displacement.max(0.0);
+  }});
+  fatten_axis!(x);
+  fatten_axis!(y);
+  fatten_axis!(z);
+  fat
+}
+
+enum Contents<V> {
+  /// An object, with its actual bounds.
+  Leaf(V, Aabb3<f32>),
+  Branch([usize; 2]),
+}
+
+struct Node<V> {
+  /// Contains everything below this node.
+  bounds: Aabb3<f32>,
+  parent: Option<usize>,
+  /// 0 for leaves.
+  height: u32,
+  contents: Contents<V>,
+}
+
+pub struct T<V> {
+  nodes: Vec<Node<V>>,
+  /// Unused indices in `nodes`.
+  free: Vec<usize>,
+  root: Option<usize>,
+  leaves: HashMap<V, usize>,
+}
+
+impl<V: Debug + Copy + Eq + Hash> T<V> {
+  pub fn new() -> T<V> {
+    T {
+      nodes: Vec::new(),
+      free: Vec::new(),
+      root: None,
+      leaves: HashMap::new(),
+    }
+  }
+
+  pub fn insert(&mut self, bounds: &Aabb3<f32>, v: V) {
+    let leaf =
+      self.allocate(Node {
+        bounds: *bounds,
+        parent: None,
+        height: 0,
+        contents: Contents::Leaf(v, *bounds),
+      });
+    let prev = self.leaves.insert(v, leaf);
+    assert!(prev.is_none(), "{:?} is already in the tree", v);
+    self.insert_leaf(leaf);
+  }
+
+  /// Remove `v` from the tree. Returns false if it wasn't there.
+  pub fn remove(&mut self, bounds: &Aabb3<f32>, v: V) -> bool {
+    match self.leaves.remove(&v) {
+      None => false,
+      Some(leaf) => {
+        debug_assert!(self.leaf_bounds(leaf) == *bounds);
+        self.remove_leaf(leaf);
+        self.free.push(leaf);
+        true
+      },
+    }
+  }
+
+  pub fn reinsert(&mut self, v: V, bounds: &Aabb3<f32>, new_bounds: &Aabb3<f32>) {
+    let leaf = self.leaves[&v];
+    debug_assert!(self.leaf_bounds(leaf) == *bounds);
+    self.nodes[leaf].contents = Contents::Leaf(v, *new_bounds);
+    if contains(&self.nodes[leaf].bounds, new_bounds) {
+      return
+    }
+
+    self.remove_leaf(leaf);
+    self.nodes[leaf].bounds = fatten(new_bounds, bounds);
+    self.insert_leaf(leaf);
+  }
+
+  /// Call `f` on every object overlapping `bounds`, except for those equal to `self_v`.
+  pub fn intersect_all<F>(&self, bounds: &Aabb3<f32>, self_v: Option<V>, f: &mut F)
+    where F: FnMut(&Aabb3<f32>, V)
+  {
+    let mut stack: Vec<usize> = self.root.into_iter().collect();
+    while let Some(i) = stack.pop() {
+      let node = &self.nodes[i];
+      if !overlap(&node.bounds, bounds) {
+        continue
+      }
+      match node.contents {
+        Contents::Leaf(v, ref leaf_bounds) => {
+          if Some(v) != self_v && overlap(leaf_bounds, bounds) {
+            f(leaf_bounds, v);
+          }
+        },
+        Contents::Branch(children) => {
+          stack.extend_from_slice(&children);
+        },
+      }
+    }
+  }
+
+  fn leaf_bounds(&self, leaf: usize) -> Aabb3<f32> {
+    match self.nodes[leaf].contents {
+      Contents::Leaf(_, bounds) => bounds,
+      Contents::Branch(_) => panic!("{} isn't a leaf", leaf),
+    }
+  }
+
+  fn allocate(&mut self, node: Node<V>) -> usize {
+    match self.free.pop() {
+      None => {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+      },
+      Some(i) => {
+        self.nodes[i] = node;
+        i
+      },
+    }
+  }
+
+  fn children(&self, i: usize) -> [usize; 2] {
+    match self.nodes[i].contents {
+      Contents::Branch(children) => children,
+      Contents::Leaf(_, _) => panic!("{} isn't a branch", i),
+    }
+  }
+
+  /// Point whatever pointed at `old` (its parent, or the root) at `new` instead.
+  fn replace_child(&mut self, parent: Option<usize>, old: usize, new: usize) {
+    match parent {
+      None => self.root = Some(new),
+      Some(parent) => {
+        let mut children = self.children(parent);
+        for child in &mut children {
+          if *child == old {
+            *child = new;
+          }
+        }
+        self.nodes[parent].contents = Contents::Branch(children);
+      },
+    }
+    self.nodes[new].parent = parent;
+  }
+
+  /// Recompute a branch's bounds and height from its children.
+  fn fix(&mut self, i: usize) {
+    let children = self.children(i);
+    let (a, b) = (children[0], children[1]);
+    self.nodes[i].bounds = union(&self.nodes[a].bounds, &self.nodes[b].bounds);
+    self.nodes[i].height = 1 + self.nodes[a].height.max(self.nodes[b].height);
+  }
+
+  /// Rebalance and refit every branch from `i` up to the root.
+  fn fix_upward(&mut self, mut i: Option<usize>) {
+    while let Some(branch) = i {
+      let branch = self.balance(branch);
+      self.fix(branch);
+      i = self.nodes[branch].parent;
+    }
+  }
+
+  fn insert_leaf(&mut self, leaf: usize) {
+    let root =
+      match self.root {
+        None => {
+          self.root = Some(leaf);
+          self.nodes[leaf].parent = None;
+          return
+        },
+        Some(root) => root,
+      };
+
+    // Find the cheapest sibling for the new leaf.
+    let bounds = self.nodes[leaf].bounds;
+    let mut sibling = root;
+    while let Contents::Branch(children) = self.nodes[sibling].contents {
+      let combined = cost(&union(&self.nodes[sibling].bounds, &bounds));
+      // Cost of making a new parent for `sibling` and the leaf.
+      let here = 2.0 * combined;
+      // Cost pushed down onto the children by growing `sibling`.
+      let inherited = 2.0 * (combined - cost(&self.nodes[sibling].bounds));
+      let descend_cost = |child: usize| {
+        let node = &self.nodes[child];
+        let grown = cost(&union(&node.bounds, &bounds));
+        match node.contents {
+          Contents::Leaf(_, _) => grown + inherited,
+          Contents::Branch(_) => grown - cost(&node.bounds) + inherited,
+        }
+      };
+      let cost0 = descend_cost(children[0]);
+      let cost1 = descend_cost(children[1]);
+      if here < cost0 && here < cost1 {
+        break
+      }
+      sibling = if cost0 < cost1 { children[0] } else { children[1] };
+    }
+
+    let old_parent = self.nodes[sibling].parent;
+    let parent =
+      self.allocate(Node {
+        bounds: union(&self.nodes[sibling].bounds, &bounds),
+        parent: None,
+        height: self.nodes[sibling].height + 1,
+        contents: Contents::Branch([sibling, leaf]),
+      });
+    self.replace_child(old_parent, sibling, parent);
+    self.nodes[sibling].parent = Some(parent);
+    self.nodes[leaf].parent = Some(parent);
+    self.fix_upward(Some(parent));
+  }
+
+  /// Detach a leaf from the tree, without freeing it.
+  fn remove_leaf(&mut self, leaf: usize) {
+    let parent =
+      match self.nodes[leaf].parent {
+        None => {
+          self.root = None;
+          return
+        },
+        Some(parent) => parent,
+      };
+
+    let children = self.children(parent);
+    let sibling = if children[0] == leaf { children[1] } else { children[0] };
+    let grandparent = self.nodes[parent].parent;
+    self.replace_child(grandparent, parent, sibling);
+    self.free.push(parent);
+    self.nodes[leaf].parent = None;
+    self.fix_upward(grandparent);
+  }
+
+  /// If one of `i`'s children is much taller than the other, rotate it up into `i`'s place.
+  /// Returns the index of the node now in `i`'s place.
+  fn balance(&mut self, i: usize) -> usize {
+    let children = self.children(i);
+    let h0 = self.nodes[children[0]].height;
+    let h1 = self.nodes[children[1]].height;
+    let k =
+      if h1 > h0 + 1 {
+        1
+      } else if h0 > h1 + 1 {
+        0
+      } else {
+        return i
+      };
+
+    let tall = children[k];
+    let grandchildren = self.children(tall);
+    let (f, g) = (grandchildren[0], grandchildren[1]);
+    // Keep the taller grandchild under `tall`, and give the shorter one to `i`.
+    let (keep, give) =
+      if self.nodes[f].height > self.nodes[g].height {
+        (f, g)
+      } else {
+        (g, f)
+      };
+
+    let parent = self.nodes[i].parent;
+    self.replace_child(parent, i, tall);
+
+    let mut i_children = children;
+    i_children[k] = give;
+    self.nodes[i].contents = Contents::Branch(i_children);
+    self.nodes[give].parent = Some(i);
+
+    self.nodes[tall].contents = Contents::Branch([i, keep]);
+    self.nodes[i].parent = Some(tall);
+
+    self.fix(i);
+    self.fix(tall);
+    tall
+  }
+}
+
+#[cfg(test)]
+mod bench {
+  use cgmath::{Aabb3, Point, Point3, Vector3};
+  use test::Bencher;
+
+  use octree::Octree;
+
+  const TERRAIN_WIDTH: i32 = 64;
+  const MOVERS: u32 = 32;
+  const FIRST_MOVER: u32 = 1 << 20;
+
+  fn terrain() -> Vec<(Aabb3<f32>, u32)> {
+    let mut boxes = Vec::new();
+    for x in 0 .. TERRAIN_WIDTH {
+      for z in 0 .. TERRAIN_WIDTH {
+        let low = Point3::new(x as f32, ((x * 7 + z * 13) % 5) as f32 - 4.0, z as f32);
+        boxes.push((Aabb3::new(low, low.add_v(&Vector3::new(1.0, 1.0, 1.0))), boxes.len() as u32));
+      }
+    }
+    boxes
+  }
+
+  fn mover(i: u32, step: u32) -> Aabb3<f32> {
+    let angle = (i as f32) + (step as f32) * 0.05;
+    let center = Point3::new(32.0 + 24.0 * angle.cos(), 2.0, 32.0 + 24.0 * angle.sin());
+    Aabb3::new(center.add_v(&Vector3::new(-0.5, -1.0, -0.5)), center.add_v(&Vector3::new(0.5, 1.0, 0.5)))
+  }
+
+  /// Move every mover one step and look for what it's touching, as the physics does each tick.
+  macro_rules! bench_movers(($tree:ident, $b:ident) => {{
+    let mut step = 0;
+    for i in 0 .. MOVERS {
+      $tree.insert(&mover(i, step), FIRST_MOVER + i);
+    }
+    $b.iter(|| {
+      let mut hits = 0;
+      for i in 0 .. MOVERS {
+        let bounds = mover(i, step + 1);
+        $tree.reinsert(FIRST_MOVER + i, &mover(i, step), &bounds);
+        $tree.intersect_all(&bounds, Some(FIRST_MOVER + i), &mut |_, _| hits += 1);
+      }
+      step += 1;
+      hits
+    });
+  }});
+
+  #[bench]
+  fn aabb_tree_movers(b: &mut Bencher) {
+    let mut tree = super::T::new();
+    for (bounds, v) in terrain() {
+      tree.insert(&bounds, v);
+    }
+    bench_movers!(tree, b);
+  }
+
+  #[bench]
+  fn octree_movers(b: &mut Bencher) {
+    let mut tree =
+      Octree::new(&Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(128.0, 128.0, 128.0)));
+    for (bounds, v) in terrain() {
+      tree.insert(&bounds, v);
+    }
+    bench_movers!(tree, b);
+  }
+
+  #[bench]
+  fn aabb_tree_insert_terrain(b: &mut Bencher) {
+    let terrain = terrain();
+    b.iter(|| {
+      let mut tree = super::T::new();
+      for &(bounds, v) in &terrain {
+        tree.insert(&bounds, v);
+      }
+      tree
+    });
+  }
+
+  #[bench]
+  fn octree_insert_terrain(b: &mut Bencher) {
+    let terrain = terrain();
+    b.iter(|| {
+      let mut tree =
+        Octree::new(&Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(128.0, 128.0, 128.0)));
+      for &(bounds, v) in &terrain {
+        tree.insert(&bounds, v);
+      }
+      tree
+    });
+  }
+}
+
+#[test]
+fn finds_what_it_was_given() {
+  let mut tree = T::new();
+  let cube = |x: f32| Aabb3::new(Point3::new(x, 0.0, 0.0), Point3::new(x + 1.0, 1.0, 1.0));
+  for i in 0 .. 100 {
+    tree.insert(&cube(i as f32), i);
+  }
+  tree.reinsert(50, &cube(50.0), &cube(150.0));
+  assert!(tree.remove(&cube(10.0), 10));
+  assert!(!tree.remove(&cube(10.0), 10));
+
+  let find = |tree: &T<i32>, x: f32| {
+    let mut found = Vec::new();
+    tree.intersect_all(&Aabb3::new(Point3::new(x, 0.5, 0.5), Point3::new(x + 2.0, 0.6, 0.6)), None, &mut |_, v| found.push(v));
+    found.sort();
+    found
+  };
+  assert_eq!(find(&tree, 48.5), vec!(48, 49));
+  assert_eq!(find(&tree, 149.5), vec!(50));
+  assert_eq!(find(&tree, 9.5), vec!(9, 11));
+
+  // The tree should stay balanced.
+  assert!(tree.nodes[tree.root.unwrap()].height < 16);
+}
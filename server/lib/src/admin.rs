@@ -0,0 +1,440 @@
+//! Commands for administering a running server. They're parsed from text, so they can come from
+//! the server's console or anywhere else that can send lines of text.
+
+use cgmath::{Aabb3, Point, Point2, Point3};
+use std::default::Default;
+use std::str::FromStr;
+use std::sync::mpsc;
+use stopwatch;
+
+use common::entity_id;
+use common::protocol;
+
+use mob;
+use queue;
+use schematic;
+use server::Server;
+use update_gaia;
+use world_file;
+
+/// Commands the server understands.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  /// List the commands.
+  Help,
+  /// List the connected clients, and what's been sent to them.
+  Clients,
+  /// List the players, with their positions and health.
+  Players,
+  /// Disconnect a client, and remove its players.
+  Kick(protocol::ClientId),
+  /// Move a player to a position.
+  Teleport(entity_id::T, Point3<f32>),
+  /// Set the sun to a [0, 1) portion of its cycle.
+  SetTime(f32),
+  /// Spawn a mob of the named kind as close to an x/z position as possible.
+  Spawn(String, Point2<f32>),
+  /// Remove a mob.
+  Despawn(entity_id::T),
+  /// Save the world's edits.
+  Save,
+  /// Print timing, network and terrain stats.
+  Stats,
+  /// Shut the server down, telling clients why.
+  Quit(String),
+  /// Copy the voxels in a box into a schematic file.
+  Copy(Aabb3<i32>, String),
+  /// Paste a schematic file with its low corner at a position.
+  Paste(String, Point3<i32>, schematic::Orientation),
+}
+
+/// What clients are told when the server shuts down without a reason.
+pub const DEFAULT_QUIT_REASON: &'static str = "The server is shutting down.";
+
+const HELP: &'static str = "\
+help                  list commands
+clients               list connected clients
+players               list players
+kick <client>         disconnect a client and remove its players
+teleport <id> <x y z> move a player
+time <fraction>       set the time of day, from 0 to 1
+spawn <kind> <x z>    spawn a mob (tag or wanderer)
+despawn <id>          remove a mob
+save                  save the world's edits
+stats                 print timing, network and terrain stats
+quit [reason]         shut the server down, telling players why
+copy <x y z> <x y z> <file>
+                      copy the voxels between two corners into a schematic file
+paste <file> <x y z> [degrees] [mirror]
+                      paste a schematic with its low corner at a position, turned a multiple
+                      of 90 degrees around y and optionally mirrored along x first";
+
+fn number<X: FromStr>(word: Option<&str>, what: &str) -> Result<X, String> {
+  match word {
+    None => Err(format!("Expected {}.", what)),
+    Some(word) => word.parse().map_err(|_| format!("Expected {}, got {:?}.", what, word)),
+  }
+}
+
+fn entity(word: Option<&str>) -> Result<entity_id::T, String> {
+  let id: u32 = try!(number(word, "an id"));
+  Ok(Default::default() + id)
+}
+
+fn position<'a, Words: Iterator<Item=&'a str>>(words: &mut Words) -> Result<Point3<i32>, String> {
+  let x = try!(number(words.next(), "an x coordinate"));
+  let y = try!(number(words.next(), "a y coordinate"));
+  let z = try!(number(words.next(), "a z coordinate"));
+  Ok(Point3::new(x, y, z))
+}
+
+fn file(word: Option<&str>) -> Result<String, String> {
+  match word {
+    None => Err(String::from("Expected a file.")),
+    Some(path) => Ok(String::from(path)),
+  }
+}
+
+/// Parse a line of text into a command.
+pub fn parse(line: &str) -> Result<Command, String> {
+  let mut words = line.split_whitespace();
+  let command =
+    match words.next() {
+      None => return Err(String::from("Empty command.")),
+      Some(command) => command,
+    };
+  let command =
+    match command {
+      "help" => Command::Help,
+      "clients" => Command::Clients,
+      "players" => Command::Players,
+      "kick" => {
+        let id: u32 = try!(number(words.next(), "a client id"));
+        Command::Kick(Default::default() + id)
+      },
+      "teleport" => {
+        let id = try!(entity(words.next()));
+        let x = try!(number(words.next(), "an x coordinate"));
+        let y = try!(number(words.next(), "a y coordinate"));
+        let z = try!(number(words.next(), "a z coordinate"));
+        Command::Teleport(id, Point3::new(x, y, z))
+      },
+      "time" => {
+        let fraction: f32 = try!(number(words.next(), "a fraction of the day"));
+        if fraction < 0.0 || fraction >= 1.0 {
+          return Err(format!("The time should be from 0 up to 1, not {}.", fraction))
+        }
+        Command::SetTime(fraction)
+      },
+      "spawn" => {
+        let kind =
+          match words.next() {
+            None => return Err(String::from("Expected a kind of mob.")),
+            Some(kind) => String::from(kind),
+          };
+        let x = try!(number(words.next(), "an x coordinate"));
+        let z = try!(number(words.next(), "a z coordinate"));
+        Command::Spawn(kind, Point2::new(x, z))
+      },
+      "despawn" => Command::Despawn(try!(entity(words.next()))),
+      "save" => Command::Save,
+      "stats" => Command::Stats,
+      "quit" => {
+        let reason: Vec<&str> = words.by_ref().collect();
+        if reason.is_empty() {
+          Command::Quit(String::from(DEFAULT_QUIT_REASON))
+        } else {
+          Command::Quit(reason.join(" "))
+        }
+      },
+      "copy" => {
+        let a = try!(position(&mut words));
+        let b = try!(position(&mut words));
+        // The corners can be given in any order.
+        Command::Copy(Aabb3::new(a, b), try!(file(words.next())))
+      },
+      "paste" => {
+        let path = try!(file(words.next()));
+        let target = try!(position(&mut words));
+        let mut words = words.by_ref().peekable();
+        let quarter_turns =
+          match words.peek().and_then(|word| word.parse::<u32>().ok()) {
+            None => 0,
+            Some(degrees) => {
+              words.next();
+              if degrees % 90 != 0 || degrees >= 360 {
+                return Err(format!("Pastes can be turned 0, 90, 180 or 270 degrees, not {}.", degrees))
+              }
+              (degrees / 90) as u8
+            },
+          };
+        let mirror =
+          match words.peek() {
+            Some(&"mirror") => {
+              words.next();
+              true
+            },
+            _ => false,
+          };
+        if let Some(word) = words.next() {
+          return Err(format!("Unexpected {:?} at the end of the command.", word))
+        }
+        Command::Paste(path, target, schematic::Orientation { mirror: mirror, quarter_turns: quarter_turns })
+      },
+      _ => return Err(format!("Unrecognized command {:?}; try \"help\".", command)),
+    };
+
+  match words.next() {
+    None => Ok(command),
+    Some(word) => Err(format!("Unexpected {:?} at the end of the command.", word)),
+  }
+}
+
+fn kick(server: &Server, id: protocol::ClientId) -> String {
+  let client =
+    match server.clients.borrow_mut().remove(&id) {
+      None => return format!("There's no client {:?}.", id),
+      Some(client) => client,
+    };
+  info!("Kicking client {:?}.", id);
+
+  for &player_id in &client.players {
+    let player =
+      match server.players.borrow_mut().remove(&player_id) {
+        None => continue,
+        Some(player) => player,
+      };
+    server.physics.borrow_mut().remove_misc(player_id);
+    player.unload_surroundings(server);
+
+    for (_, other) in server.clients.borrow_mut().iter_mut() {
+      other.send(protocol::ServerToClient::RemovePlayer(player_id));
+    }
+  }
+
+  format!("Kicked client {:?} and {} player(s).", id, client.players.len())
+}
+
+fn teleport(server: &Server, id: entity_id::T, position: Point3<f32>) -> String {
+  let r = server.config.world_radius;
+  if position.x.abs() > r || position.z.abs() > r {
+    return format!("That's past the edge of the world, {} from the origin along x and z.", r)
+  }
+
+  let mut players = server.players.borrow_mut();
+  let player =
+    match players.get_mut(&id) {
+      None => return format!("There's no player {:?}.", id),
+      Some(player) => player,
+    };
+
+  let mut physics = server.physics.borrow_mut();
+  let bounds =
+    match physics.get_bounds(id) {
+      None => return format!("Player {:?} isn't in the world.", id),
+      Some(bounds) => *bounds,
+    };
+  let offset = position.sub_p(&player.position);
+  let bounds = Aabb3::new(bounds.min.add_v(&offset), bounds.max.add_v(&offset));
+  physics.teleport_misc(id, &bounds);
+  player.position = position;
+
+  format!("Teleported {:?} to {:?}.", id, position)
+}
+
+fn spawn(server: &Server, kind: &str, position: &Point2<f32>) -> String {
+  let kind: Box<mob::Kind> =
+    match kind {
+      "tag" => Box::new(mob::tag::new()),
+      "wanderer" => Box::new(mob::wanderer::new()),
+      _ => return format!("There's no kind of mob called {:?}.", kind),
+    };
+  match mob::spawn(server, kind, position) {
+    None => String::from("There's nowhere to put it."),
+    Some(id) => format!("Spawned {:?}.", id),
+  }
+}
+
+fn copy(server: &Server, bounds: &Aabb3<i32>, path: &str) -> String {
+  let size = bounds.max.sub_p(&bounds.min);
+  let count = size.x as i64 * size.y as i64 * size.z as i64;
+  if count == 0 {
+    return String::from("There's nothing between those corners.")
+  }
+  if count > schematic::MAX_VOXELS {
+    return format!("That's {} voxels; schematics can hold up to {}.", count, schematic::MAX_VOXELS)
+  }
+  let schematic =
+    match schematic::copy(&server.terrain_loader.terrain, bounds) {
+      Ok(schematic) => schematic,
+      Err(unloaded) => return format!("{:?} isn't loaded; only loaded terrain (e.g. around players) can be copied.", unloaded),
+    };
+  match schematic.save(path) {
+    Ok(()) => format!("Copied {:?} voxels to {}.", size, path),
+    Err(err) => err,
+  }
+}
+
+/// Run a command against the world, and describe what happened.
+/// Changes to the terrain are sent through `update_gaia`, like clients' edits.
+pub fn execute<UpdateGaia>(
+  server: &Server,
+  update_gaia: &mut UpdateGaia,
+  command: Command,
+) -> String where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  match command {
+    Command::Help => String::from(HELP),
+    Command::Clients => {
+      let clients = server.clients.borrow_mut();
+      let mut ids: Vec<&protocol::ClientId> = clients.keys().collect();
+      ids.sort();
+      let mut out = format!("{} client(s)", ids.len());
+      for id in ids {
+        let client = &clients[id];
+        out.push_str(&format!(
+          "\n{:?}: players {:?}, sent {} messages ({} KB)",
+          id,
+          client.players,
+          client.sent.messages,
+          client.sent.bytes / 1024
+        ));
+      }
+      out
+    },
+    Command::Players => {
+      let players = server.players.borrow_mut();
+      let mut out = format!("{} player(s)", players.len());
+      for (id, player) in players.iter() {
+        out.push_str(&format!(
+          "\n{:?}: at ({:.1}, {:.1}, {:.1}), health {}{}",
+          id,
+          player.position.x, player.position.y, player.position.z,
+          player.health,
+          match player.respawn_ticks {
+            None => "",
+            Some(_) if player.health == 0 => " (dead)",
+            Some(_) => " (spawning)",
+          }
+        ));
+      }
+      out
+    },
+    Command::Kick(id) => kick(server, id),
+    Command::Teleport(id, position) => teleport(server, id, position),
+    Command::SetTime(fraction) => {
+      // The sun's position is squared to get the time of day; see `Sun::update`.
+      server.sun.borrow_mut().position = (fraction.sqrt() * 65536.0) as u16;
+      format!("Set the time to {}.", fraction)
+    },
+    Command::Spawn(kind, position) => spawn(server, &kind, &position),
+    Command::Despawn(id) => {
+      if !server.mobs.borrow_mut().contains_key(&id) {
+        return format!("There's no mob {:?}.", id)
+      }
+      mob::despawn(server, id);
+      format!("Despawned {:?}.", id)
+    },
+    Command::Save => {
+      let path = &server.config.world_file;
+      match world_file::save(&server.edits.borrow(), path) {
+        Ok(()) => format!("Saved {} edited voxels to {}.", server.edits.borrow().len(), path),
+        Err(err) => err,
+      }
+    },
+    Command::Stats => {
+      stopwatch::clone().print();
+      let received = *server.received.borrow();
+      let (sent_messages, sent_bytes) =
+        server.clients.borrow_mut().values()
+        .fold((0, 0), |(m, b), client| (m + client.sent.messages, b + client.sent.bytes));
+      let voxels = server.terrain_loader.terrain.voxels.stats();
+      format!(
+        "Timing stats printed to the log.\n\
+         Received {} messages ({} KB), sent {} messages ({} KB) to current clients.\n\
+         {} voxel regions ({} edited), {} KB.",
+        received.messages, received.bytes / 1024,
+        sent_messages, sent_bytes / 1024,
+        voxels.regions, voxels.edited_regions, voxels.bytes / 1024
+      )
+    },
+    Command::Quit(reason) => {
+      *server.shutdown.borrow_mut() = Some(reason);
+      String::from("Shutting down.")
+    },
+    Command::Copy(bounds, path) => copy(server, &bounds, &path),
+    Command::Paste(path, target, orientation) => {
+      match schematic::load(&path) {
+        Err(err) => err,
+        Ok(schematic) => {
+          let size = orientation.turn_size(&schematic.size);
+          update_gaia(update_gaia::Message::Brush(schematic::paste(schematic, &target, orientation), None));
+          format!("Pasting {:?} voxels at {:?}.", size, target)
+        },
+      }
+    },
+  }
+}
+
+/// A command to run, and where to send the result.
+pub struct Request {
+  #[allow(missing_docs)]
+  pub command: Command,
+  #[allow(missing_docs)]
+  pub reply: mpsc::Sender<String>,
+}
+
+/// Where commands wait for the server to run them.
+pub struct Inbox {
+  #[allow(missing_docs)]
+  pub requests: queue::T<Request>,
+}
+
+#[allow(missing_docs)]
+pub fn inbox() -> Inbox {
+  Inbox {
+    requests: queue::new(),
+  }
+}
+
+impl Inbox {
+  /// Have the server run `command`, and wait for the result.
+  pub fn run(&self, command: Command) -> String {
+    let (reply, result) = mpsc::channel();
+    self.requests.push(Request { command: command, reply: reply });
+    match result.recv() {
+      Ok(result) => result,
+      Err(_) => String::from("The server stopped before running the command."),
+    }
+  }
+}
+
+#[test]
+fn parses_commands() {
+  assert_eq!(parse("time 0.5"), Ok(Command::SetTime(0.5)));
+  assert_eq!(
+    parse("  teleport 3 1 2.5 -4\n"),
+    Ok(Command::Teleport(Default::default() + 3, Point3::new(1.0, 2.5, -4.0)))
+  );
+  assert_eq!(parse("spawn tag 1 2"), Ok(Command::Spawn(String::from("tag"), Point2::new(1.0, 2.0))));
+  assert!(parse("time 2").is_err());
+  assert!(parse("kick").is_err());
+  assert!(parse("save now").is_err());
+  assert_eq!(parse("quit back in 5"), Ok(Command::Quit(String::from("back in 5"))));
+  assert!(parse("fly").is_err());
+  assert_eq!(
+    parse("copy 4 0 -2 0 8 2 house.schematic"),
+    Ok(Command::Copy(Aabb3::new(Point3::new(0, 0, -2), Point3::new(4, 8, 2)), String::from("house.schematic")))
+  );
+  assert_eq!(
+    parse("paste house.schematic 1 2 3 90 mirror"),
+    Ok(Command::Paste(
+      String::from("house.schematic"),
+      Point3::new(1, 2, 3),
+      schematic::Orientation { mirror: true, quarter_turns: 1 }
+    ))
+  );
+  assert!(parse("paste house.schematic 1 2 3 45").is_err());
+  assert!(parse("paste house.schematic 1 2 3 upside_down").is_err());
+}
@@ -0,0 +1,330 @@
+//! Turn players' brush descriptions into edits to the terrain.
+
+use cgmath::{Aabb3, EuclideanVector, Point, Point3, Vector, Vector3};
+use std::collections::HashMap;
+
+use common::protocol;
+use common::voxel;
+
+use terrain;
+
+/// A brush ready to be applied to the terrain.
+pub type T = voxel::brush::T<Box<voxel::mosaic::T<voxel::Material> + Send>>;
+
+/// The smallest a brush can be along any axis.
+const MIN_EXTENT: f32 = 0.5;
+
+/// Approximate the outward normal of a field from how its density changes around `p`.
+pub fn normal_of<Field: voxel::field::T>(field: &Field, p: &Point3<f32>) -> Vector3<f32> {
+  let delta = 0.01;
+  let differential = |v: Vector3<f32>| {
+    voxel::field::T::density(field, &p.add_v(&v)) - voxel::field::T::density(field, &p.add_v(&-v))
+  };
+  let v =
+    Vector3::new(
+      differential(Vector3::new(delta, 0.0, 0.0)),
+      differential(Vector3::new(0.0, delta, 0.0)),
+      differential(Vector3::new(0.0, 0.0, delta)),
+    );
+  // Negate because we're leaving the volume when density is decreasing.
+  let v = -v;
+  if v.length2() == 0.0 {
+    Vector3::new(0.0, 1.0, 0.0)
+  } else {
+    v.normalize()
+  }
+}
+
+/// A brush's shape, centered on the origin.
+struct Shape {
+  shape: protocol::BrushShape,
+  extents: Vector3<f32>,
+}
+
+impl voxel::field::T for Shape {
+  fn density(&self, p: &Point3<f32>) -> f32 {
+    let e = self.extents;
+    // Scale distances measured relative to the extents back to (roughly) world units.
+    let scale = e.x.min(e.y).min(e.z);
+    let lateral = ((p.x / e.x).powi(2) + (p.z / e.z).powi(2)).sqrt();
+    let slab = e.y - p.y.abs();
+    match self.shape {
+      protocol::BrushShape::Sphere => {
+        (1.0 - ((p.x / e.x).powi(2) + (p.y / e.y).powi(2) + (p.z / e.z).powi(2)).sqrt()) * scale
+      },
+      protocol::BrushShape::Cube => {
+        (e.x - p.x.abs()).min(slab).min(e.z - p.z.abs())
+      },
+      protocol::BrushShape::Cylinder => {
+        ((1.0 - lateral) * scale).min(slab)
+      },
+      protocol::BrushShape::Cone => {
+        // The radius shrinks from the full extents at the bottom to nothing at the top.
+        let radius = (1.0 - p.y / e.y) / 2.0;
+        ((radius - lateral) * scale).min(slab)
+      },
+    }
+  }
+
+  fn normal(&self, p: &Point3<f32>) -> Vector3<f32> {
+    normal_of(self, p)
+  }
+}
+
+/// A copy of the finest voxels in part of the terrain, so they can be read while brushing over them.
+struct Snapshot {
+  voxels: HashMap<voxel::bounds::T, voxel::T>,
+}
+
+/// Copy the voxels in `bounds`. Nothing is generated, so this fails with the first voxel that
+/// isn't loaded.
+fn snapshot(terrain: &terrain::T, bounds: &Aabb3<i32>) -> Result<Snapshot, voxel::bounds::T> {
+  let mut voxels = HashMap::new();
+  for x in bounds.min.x .. bounds.max.x {
+    for y in bounds.min.y .. bounds.max.y {
+      for z in bounds.min.z .. bounds.max.z {
+        let bounds = voxel::bounds::new(x, y, z, 0);
+        match terrain.get(&bounds) {
+          None => return Err(bounds),
+          Some(voxel) => {
+            voxels.insert(bounds, voxel);
+          },
+        }
+      }
+    }
+  }
+  Ok(Snapshot {
+    voxels: voxels,
+  })
+}
+
+/// The material of a voxel. Surface voxels are treated as made of their corner material.
+pub fn material_of(voxel: Option<voxel::T>) -> voxel::Material {
+  match voxel {
+    Some(voxel::Volume(material)) => material,
+    Some(voxel::Surface(voxel)) => voxel.corner,
+    None => voxel::Material::Empty,
+  }
+}
+
+/// Approximate the density at `p` from the voxel at `bounds`, which contains it.
+pub fn density_in(bounds: &voxel::bounds::T, voxel: Option<voxel::T>, p: &Point3<f32>) -> f32 {
+  match voxel {
+    Some(voxel::Volume(voxel::Material::Empty)) | None => -1.0,
+    Some(voxel::Volume(_)) => 1.0,
+    Some(voxel::Surface(voxel)) => {
+      // Approximate the surface within the voxel as a plane.
+      let vertex = voxel.surface_vertex.to_world_vertex(bounds);
+      let normal = voxel.normal.to_float_normal();
+      // Normals point out of the volume.
+      normal.dot(&vertex.sub_p(p))
+    },
+  }
+}
+
+impl Snapshot {
+  fn voxel_at(&self, p: &Point3<f32>) -> (voxel::bounds::T, Option<voxel::T>) {
+    let bounds = voxel::bounds::new(p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32, 0);
+    (bounds, self.voxels.get(&bounds).cloned())
+  }
+
+  fn material_at(&self, p: &Point3<f32>) -> voxel::Material {
+    material_of(self.voxel_at(p).1)
+  }
+}
+
+impl voxel::field::T for Snapshot {
+  fn density(&self, p: &Point3<f32>) -> f32 {
+    let (bounds, voxel) = self.voxel_at(p);
+    density_in(&bounds, voxel, p)
+  }
+
+  fn normal(&self, p: &Point3<f32>) -> Vector3<f32> {
+    match self.voxel_at(p) {
+      (_, Some(voxel::Surface(voxel))) => voxel.normal.to_float_normal(),
+      _ => normal_of(self, p),
+    }
+  }
+}
+
+/// Changes the material of the terrain inside a shape, keeping the terrain's shape.
+struct Replace {
+  shape: voxel::field::translation::T<Shape>,
+  before: Snapshot,
+  material: voxel::Material,
+}
+
+impl voxel::field::T for Replace {
+  fn density(&self, p: &Point3<f32>) -> f32 {
+    voxel::field::T::density(&self.before, p)
+  }
+
+  fn normal(&self, p: &Point3<f32>) -> Vector3<f32> {
+    voxel::field::T::normal(&self.before, p)
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for Replace {
+  fn material(&self, p: &Point3<f32>) -> Option<voxel::Material> {
+    if voxel::field::T::density(&self.shape, p) < 0.0 {
+      None
+    } else if voxel::field::T::density(&self.before, p) < 0.0 {
+      Some(voxel::Material::Empty)
+    } else {
+      Some(self.material)
+    }
+  }
+}
+
+/// The offsets sampled to smooth the terrain.
+const SMOOTHING_SAMPLES: [[f32; 3]; 7] = [
+  [0.0, 0.0, 0.0],
+  [1.0, 0.0, 0.0], [-1.0, 0.0, 0.0],
+  [0.0, 1.0, 0.0], [0.0, -1.0, 0.0],
+  [0.0, 0.0, 1.0], [0.0, 0.0, -1.0],
+];
+
+/// Blurs the terrain inside a shape.
+struct Smooth {
+  shape: voxel::field::translation::T<Shape>,
+  before: Snapshot,
+}
+
+impl voxel::field::T for Smooth {
+  fn density(&self, p: &Point3<f32>) -> f32 {
+    let total =
+      SMOOTHING_SAMPLES.iter()
+      .map(|d| voxel::field::T::density(&self.before, &p.add_v(&Vector3::new(d[0], d[1], d[2]))))
+      .fold(0.0, |x, y| x + y);
+    total / SMOOTHING_SAMPLES.len() as f32
+  }
+
+  fn normal(&self, p: &Point3<f32>) -> Vector3<f32> {
+    normal_of(self, p)
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for Smooth {
+  fn material(&self, p: &Point3<f32>) -> Option<voxel::Material> {
+    if voxel::field::T::density(&self.shape, p) < 0.0 {
+      return None
+    }
+    if voxel::field::T::density(self, p) < 0.0 {
+      return Some(voxel::Material::Empty)
+    }
+    // Fill in with whatever's nearby.
+    let material =
+      SMOOTHING_SAMPLES.iter()
+      .map(|d| self.before.material_at(&p.add_v(&Vector3::new(d[0], d[1], d[2]))))
+      .find(|&material| material != voxel::Material::Empty)
+      .unwrap_or(voxel::Material::Dirt);
+    Some(material)
+  }
+}
+
+/// Check that a brush is within the limits of what players can do, e.g. `max_extent`.
+pub fn validate(brush: &protocol::Brush, max_extent: f32) -> Result<(), String> {
+  for &extent in &[brush.extents.x, brush.extents.y, brush.extents.z] {
+    let in_range = MIN_EXTENT <= extent && extent <= max_extent;
+    if !in_range {
+      return Err(format!("Brush extents should be from {} to {}, not {:?}.", MIN_EXTENT, max_extent, brush.extents))
+    }
+  }
+  match brush.mode {
+    protocol::BrushMode::Add | protocol::BrushMode::ReplaceMaterial => {
+      if brush.material == voxel::Material::Empty {
+        return Err(format!("{:?} brushes need a material other than Empty.", brush.mode))
+      }
+    },
+    protocol::BrushMode::Remove | protocol::BrushMode::Smooth => {},
+  }
+  Ok(())
+}
+
+/// Make a brush to apply `description` centered at `center`.
+/// The brush only reads the terrain as it is now, so it should be applied promptly.
+/// Brushes that read the terrain (replacing and smoothing) can only be made where it's loaded.
+pub fn new(terrain: &terrain::T, description: &protocol::Brush, center: &Point3<f32>) -> Result<T, String> {
+  let shape =
+    voxel::field::translation::T {
+      translation: center.to_vec(),
+      field: Shape {
+        shape: description.shape,
+        extents: description.extents,
+      },
+    };
+
+  let r = description.extents.add_v(&Vector3::new(1.0, 1.0, 1.0));
+  let bounds =
+    Aabb3::new(
+      {
+        let low = center.add_v(&-r);
+        Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
+      },
+      {
+        let high = center.add_v(&r);
+        Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
+      },
+    );
+  // Smoothing samples the voxels just outside the brush too.
+  let snapshot_bounds =
+    Aabb3::new(bounds.min.add_v(&Vector3::new(-1, -1, -1)), bounds.max.add_v(&Vector3::new(1, 1, 1)));
+
+  let before = || {
+    snapshot(terrain, &snapshot_bounds)
+    .map_err(|bounds| format!("The terrain at {:?} isn't loaded.", bounds))
+  };
+
+  let mosaic: Box<voxel::mosaic::T<voxel::Material> + Send> =
+    match description.mode {
+      protocol::BrushMode::Add => {
+        Box::new(voxel::mosaic::solid::T {
+          field: shape,
+          material: description.material,
+        })
+      },
+      protocol::BrushMode::Remove => {
+        Box::new(voxel::mosaic::solid::T {
+          field: shape,
+          material: voxel::Material::Empty,
+        })
+      },
+      protocol::BrushMode::ReplaceMaterial => {
+        Box::new(Replace {
+          shape: shape,
+          before: try!(before()),
+          material: description.material,
+        })
+      },
+      protocol::BrushMode::Smooth => {
+        Box::new(Smooth {
+          shape: shape,
+          before: try!(before()),
+        })
+      },
+    };
+
+  Ok(voxel::brush::T {
+    bounds: bounds,
+    mosaic: mosaic,
+    min_lg_size: 0,
+  })
+}
+
+#[test]
+fn shapes_fit_their_extents() {
+  let extents = Vector3::new(2.0, 4.0, 3.0);
+  for &shape in &[
+    protocol::BrushShape::Sphere,
+    protocol::BrushShape::Cube,
+    protocol::BrushShape::Cylinder,
+    protocol::BrushShape::Cone,
+  ] {
+    let shape = Shape { shape: shape, extents: extents };
+    let density = |x, y, z| voxel::field::T::density(&shape, &Point3::new(x, y, z));
+    assert!(density(0.0, 0.0, 0.0) > 0.0);
+    assert!(density(2.5, 0.0, 0.0) < 0.0);
+    assert!(density(0.0, 4.5, 0.0) < 0.0);
+    assert!(density(0.0, 0.0, -3.5) < 0.0);
+  }
+}
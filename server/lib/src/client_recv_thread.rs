@@ -1,4 +1,4 @@
-use cgmath::{Point, Point3, Vector, Vector3, Aabb3};
+use cgmath::{Point, Point3, Ray3, Vector, Vector3, Aabb3, EuclideanVector};
 use rand;
 use rand::distributions::IndependentSample;
 use std::convert::AsRef;
@@ -8,13 +8,14 @@ use std::time::Duration;
 use stopwatch;
 
 use common::entity_id;
-use common::id_allocator;
 use common::protocol;
 use common::socket::SendSocket;
 use common::voxel;
 
+use brush;
 use player::Player;
 use server::{Client, Server};
+use spawn;
 use terrain;
 use voxel_data;
 use update_gaia;
@@ -24,26 +25,59 @@ fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
   bounds.min.add_v(&bounds.max.to_vec()).mul_s(1.0 / 2.0)
 }
 
+/// Check the direction a client aimed an edit in against where the server thinks the player is
+/// `facing`. Rotations arrive separately from edits, so the two can disagree by up to `tolerance_degrees`.
+fn check_aim(facing: &Vector3<f32>, aim: &Vector3<f32>, tolerance_degrees: f32) -> Option<Vector3<f32>> {
+  // This also rejects NaNs.
+  if !(aim.length2() > 0.0) {
+    return None
+  }
+  let aim = aim.normalize();
+  let cos_angle = aim.dot(&facing.normalize());
+  if cos_angle >= tolerance_degrees.to_radians().cos() {
+    Some(aim)
+  } else {
+    None
+  }
+}
+
+/// Find the first solid voxel within reach of a player, in the direction they aimed.
 fn cast(
   server: &Server,
   player_id: entity_id::T,
+  aim: &Vector3<f32>,
 ) -> Option<voxel::bounds::T> {
-  let ray;
-  {
-    let players = server.players.lock().unwrap();
-    let player = players.get(&player_id).unwrap();
-    ray = player.forward_ray();
-  }
+  let (position, facing) =
+    match server.players.borrow_mut().get(&player_id) {
+      None => return None,
+      Some(player) => (player.position, player.forward()),
+    };
 
-  server.terrain_loader.terrain.voxels.lock().unwrap().cast_ray(
-    &ray,
-    &mut |bounds, voxel| {
-      match voxel {
-        &voxel::Volume(voxel::Material::Empty) => None,
-        _ => Some(bounds),
+  let aim =
+    match check_aim(&facing, aim, server.config.aim_tolerance_degrees) {
+      None => {
+        warn!("{:?} aimed at {:?}, but is facing {:?}; ignoring their edit.", player_id, aim, facing);
+        return None
+      },
+      Some(aim) => aim,
+    };
+
+  let max_reach = server.config.max_reach;
+  let hit =
+    server.terrain_loader.terrain.voxels.cast_ray(
+      &Ray3::new(position, aim),
+      &mut |bounds, voxel| {
+        if bounds.center().sub_p(&position).length2() > max_reach * max_reach {
+          // Everything else along the ray is further away.
+          return Some(None)
+        }
+        match voxel {
+          &voxel::Volume(voxel::Material::Empty) => None,
+          _ => Some(Some(bounds)),
+        }
       }
-    }
-  )
+    );
+  hit.and_then(|hit| hit)
 }
 
 pub fn apply_client_update<UpdateGaia>(
@@ -58,34 +92,46 @@ pub fn apply_client_update<UpdateGaia>(
       protocol::ClientToServer::Init(client_url) => {
         info!("Sending to {}.", client_url);
 
-        let mut client =
-          Client {
-            socket: SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(30))),
-          };
+        let mut client = Client::new(SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(server.config.client_timeout_secs))));
 
-        let client_id = id_allocator::allocate(&server.client_allocator);
+        let client_id = server.client_allocator.borrow_mut().allocate();
         client.send(protocol::ServerToClient::LeaseId(client_id));
 
-        server.clients.lock().unwrap().insert(client_id, client);
+        server.clients.borrow_mut().insert(client_id, client);
       },
       protocol::ClientToServer::Ping(client_id) => {
-        server.clients.lock().unwrap()
-          .get_mut(&client_id)
-          .unwrap()
-          .send(protocol::ServerToClient::Ping);
+        match server.clients.borrow_mut().get_mut(&client_id) {
+          // The client may have been kicked.
+          None => warn!("Ping from unknown client {:?}", client_id),
+          Some(client) => client.send(protocol::ServerToClient::Ping),
+        }
       },
       protocol::ClientToServer::AddPlayer(client_id) => {
+        if !server.clients.borrow_mut().contains_key(&client_id) {
+          warn!("AddPlayer from unknown client {:?}", client_id);
+          return
+        }
+
         let mut player =
           Player::new(
-            id_allocator::allocate(&server.id_allocator),
+            server.id_allocator.borrow_mut().allocate(),
             &server.owner_allocator,
+            server.config.player_load_distance,
+            server.config.max_undo_steps,
           );
 
-        // TODO: shift upward until outside terrain
-        let min = Point3::new(0.0, 64.0, 4.0);
-        let max = min.add_v(&Vector3::new(1.0, 2.0, 1.0));
-        let bounds = Aabb3::new(min, max);
-        server.physics.lock().unwrap().insert_misc(player.entity_id, &bounds);
+        let size = Vector3::new(1.0, 2.0, 1.0);
+        let min =
+          match spawn::world_spawn(server, &size) {
+            Some(min) => min,
+            None => {
+              // Hold the player above the world spawn until there's somewhere to put them.
+              player.respawn_ticks = Some(0);
+              spawn::above_world_spawn(server)
+            },
+          };
+        let bounds = Aabb3::new(min, min.add_v(&size));
+        server.physics.borrow_mut().insert_misc(player.entity_id, &bounds);
 
         player.position = center(&bounds);
         player.rotate_lateral(PI / 2.0);
@@ -93,17 +139,23 @@ pub fn apply_client_update<UpdateGaia>(
         let id = player.entity_id;
         let pos = player.position;
 
-        server.players.lock().unwrap().insert(id, player);
+        server.players.borrow_mut().insert(id, player);
 
-        let mut clients = server.clients.lock().unwrap();
+        let mut clients = server.clients.borrow_mut();
         let client = clients.get_mut(&client_id).unwrap();
+        client.players.push(id);
         client.send(
-          protocol::ServerToClient::PlayerAdded(id, pos)
+          protocol::ServerToClient::PlayerAdded(id, pos, server.config.max_brush_extent)
         );
       },
       protocol::ClientToServer::StartJump(player_id) => {
-        let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
+        let mut players = server.players.borrow_mut();
+        let player =
+          match players.get_mut(&player_id) {
+            // The player may have been kicked.
+            None => return,
+            Some(player) => player,
+          };
         if !player.is_jumping {
           player.is_jumping = true;
           // this 0.3 is duplicated in a few places
@@ -111,8 +163,13 @@ pub fn apply_client_update<UpdateGaia>(
         }
       },
       protocol::ClientToServer::StopJump(player_id) => {
-        let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
+        let mut players = server.players.borrow_mut();
+        let player =
+          match players.get_mut(&player_id) {
+            // The player may have been kicked.
+            None => return,
+            Some(player) => player,
+          };
         if player.is_jumping {
           player.is_jumping = false;
           // this 0.3 is duplicated in a few places
@@ -120,24 +177,34 @@ pub fn apply_client_update<UpdateGaia>(
         }
       },
       protocol::ClientToServer::Walk(player_id, v) => {
-        let mut players = server.players.lock().unwrap();
-        let mut player = players.get_mut(&player_id).unwrap();
+        let mut players = server.players.borrow_mut();
+        let player =
+          match players.get_mut(&player_id) {
+            // The player may have been kicked.
+            None => return,
+            Some(player) => player,
+          };
         player.walk(v);
       },
       protocol::ClientToServer::RotatePlayer(player_id, v) => {
-        let mut players = server.players.lock().unwrap();
-        let mut player = players.get_mut(&player_id).unwrap();
+        let mut players = server.players.borrow_mut();
+        let player =
+          match players.get_mut(&player_id) {
+            // The player may have been kicked.
+            None => return,
+            Some(player) => player,
+          };
         player.rotate_lateral(v.x);
         player.rotate_vertical(v.y);
       },
       protocol::ClientToServer::RequestVoxels(client_id, bounds) => {
         update_gaia(update_gaia::Message::Load(bounds, LoadReason::ForClient(client_id)));
       },
-      protocol::ClientToServer::Add(player_id) => {
-        let bounds = cast(server, player_id);
+      protocol::ClientToServer::PlantTree(player_id, aim) => {
+        let bounds = cast(server, player_id, &aim);
 
         bounds.map(|bounds| {
-          let mut rng = server.rng.lock().unwrap();
+          let mut rng = server.rng.borrow_mut();
           let rng = rng.deref_mut();
 
           let trunk_radius =
@@ -192,47 +259,39 @@ pub fn apply_client_update<UpdateGaia>(
               min_lg_size: 0,
             };
 
-          update_gaia(update_gaia::Message::Brush(brush));
+          update_gaia(update_gaia::Message::Brush(brush, Some(player_id)));
         });
       },
-      protocol::ClientToServer::Remove(player_id) => {
-        let bounds = cast(server, player_id);
+      protocol::ClientToServer::Brush(player_id, aim, description) => {
+        if let Err(err) = brush::validate(&description, server.config.max_brush_extent) {
+          warn!("Rejecting brush from {:?}: {}", player_id, err);
+          return
+        }
 
-        bounds.map(|bounds| {
-          debug!("remove bounds {:?}", bounds);
-          let center = bounds.center();
-          let r = 8.0;
-          let sphere =
-            voxel_data::mosaic::solid::T {
-              field: voxel_data::field::translation::T {
-                translation: center.to_vec(),
-                field: voxel_data::field::sphere::T {
-                  radius: r,
-                },
-              },
-              material: voxel::Material::Empty,
-            };
-          let r = sphere.field.field.radius + 1.0;
-          let brush =
-            voxel_data::brush::T {
-              bounds:
-                Aabb3::new(
-                  {
-                    let low = sphere.field.translation.add_v(&-Vector3::new(r, r, r));
-                    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
-                  },
-                  {
-                    let high = sphere.field.translation.add_v(&Vector3::new(r, r, r));
-                    Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
-                  },
-                ),
-              mosaic: Box::new(sphere) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
-              min_lg_size: 0,
-            };
-          let brush: voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>> = brush;
-          update_gaia(update_gaia::Message::Brush(brush));
-        });
+        if let Some(bounds) = cast(server, player_id, &aim) {
+          debug!("{:?} brush at {:?}", description.mode, bounds);
+          match brush::new(&server.terrain_loader.terrain, &description, &bounds.center()) {
+            Err(err) => warn!("Rejecting brush from {:?}: {}", player_id, err),
+            Ok(brush) => update_gaia(update_gaia::Message::Brush(brush, Some(player_id))),
+          }
+        }
+      },
+      protocol::ClientToServer::Undo(player_id) => {
+        update_gaia(update_gaia::Message::Undo(player_id));
+      },
+      protocol::ClientToServer::Redo(player_id) => {
+        update_gaia(update_gaia::Message::Redo(player_id));
       },
     };
   })
 }
+
+#[test]
+fn aim_is_checked_against_facing() {
+  let facing = Vector3::new(0.0, 0.0, -1.0);
+  assert!(check_aim(&facing, &Vector3::new(0.0, 0.0, -2.0), 15.0).is_some());
+  assert!(check_aim(&facing, &Vector3::new(0.1, 0.0, -1.0), 15.0).is_some());
+  assert!(check_aim(&facing, &Vector3::new(1.0, 0.0, -1.0), 15.0).is_none());
+  assert!(check_aim(&facing, &Vector3::new(0.0, 0.0, 1.0), 15.0).is_none());
+  assert!(check_aim(&facing, &Vector3::new(0.0, 0.0, 0.0), 180.0).is_none());
+}
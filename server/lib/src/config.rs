@@ -0,0 +1,189 @@
+//! The server's tunable settings, and the file they're loaded from.
+//!
+//! The file is a JSON object, e.g. `{ "updates_per_second": 60, "seed": 7 }`.
+//! Any setting that's left out keeps its default.
+
+use cgmath::Point2;
+use rustc_serialize::json::Json;
+use std::default::Default;
+use std::fs::File;
+use std::io::Read;
+
+use physics;
+
+/// The server's settings.
+#[derive(Debug, Clone)]
+pub struct T {
+  /// How many times a second the world is updated.
+  pub updates_per_second: u64,
+  /// How far the sun moves each update, in 65536ths of its cycle.
+  pub sun_step: u16,
+  /// Seeds both the terrain and the server's other randomness (e.g. mob spawning).
+  pub seed: u32,
+  /// Anything that falls below this height is considered lost.
+  pub kill_plane: f32,
+  /// How long sends to a client can block before they're given up on, in seconds.
+  pub client_timeout_secs: u64,
+  /// How far around themselves players load terrain, in blocks.
+  pub player_load_distance: i32,
+  /// How many updates players can keep accelerating upward for when they jump.
+  pub max_jump_fuel: u32,
+  /// How high a ledge players and mobs can walk up without jumping.
+  pub max_step_height: f32,
+  /// Unedited voxels are evicted once the voxel tree is estimated to use more than this many MiB.
+  pub voxel_memory_budget_mib: usize,
+  /// How far players and mobs can go from the origin along x and z. Positions are `f32`s, which
+  /// get coarser further out: at 65536 they resolve 1/128 of a unit, and small movements start
+  /// getting rounded away not far past that.
+  pub world_radius: f32,
+  /// New players are put on the ground as close to this x/z position as possible.
+  /// It has to be within `world_radius`.
+  pub world_spawn: Point2<f32>,
+  /// Spots for new entities are looked for by dropping them from this height...
+  pub spawn_search_top: f32,
+  /// ...down to this height.
+  pub spawn_search_bottom: f32,
+  /// How far away from the requested x/z position to look for a spot, if it's blocked.
+  pub spawn_search_radius: f32,
+  /// The most blocks mobs can keep fully loaded between them.
+  pub max_mob_loaded_blocks: usize,
+  /// Where the world's edits are saved and loaded.
+  pub world_file: String,
+  /// The biggest brush players can use, along any axis from its center.
+  pub max_brush_extent: f32,
+  /// How far away players can edit the terrain, in blocks.
+  pub max_reach: f32,
+  /// How far, in degrees, the direction a player aims an edit in can be from where the server
+  /// thinks they're looking.
+  pub aim_tolerance_degrees: f32,
+  /// How many of their edits each player can undo.
+  pub max_undo_steps: usize,
+}
+
+impl Default for T {
+  fn default() -> T {
+    T {
+      updates_per_second: 30,
+      sun_step: 21,
+      seed: 0,
+      kill_plane: -256.0,
+      client_timeout_secs: 30,
+      player_load_distance: 8,
+      max_jump_fuel: 4,
+      max_step_height: physics::MAX_STEP_HEIGHT,
+      voxel_memory_budget_mib: 1024,
+      world_radius: 65536.0,
+      world_spawn: Point2::new(0.0, 4.0),
+      spawn_search_top: 128.0,
+      spawn_search_bottom: -128.0,
+      spawn_search_radius: 2.0,
+      max_mob_loaded_blocks: 4096,
+      world_file: String::from("world.bin"),
+      max_brush_extent: 16.0,
+      max_reach: 64.0,
+      aim_tolerance_degrees: 15.0,
+      max_undo_steps: 32,
+    }
+  }
+}
+
+fn integer(key: &str, value: &Json, min: u64, max: u64) -> Result<u64, String> {
+  match value.as_u64() {
+    Some(x) if min <= x && x <= max => Ok(x),
+    _ => Err(format!("{} should be a whole number from {} to {}, not {}.", key, min, max, value)),
+  }
+}
+
+fn number(key: &str, value: &Json, min: f32, max: f32) -> Result<f32, String> {
+  match value.as_f64() {
+    Some(x) if min as f64 <= x && x <= max as f64 => Ok(x as f32),
+    _ => Err(format!("{} should be a number from {} to {}, not {}.", key, min, max, value)),
+  }
+}
+
+/// Read settings from JSON text. Unknown or out-of-range settings are errors.
+pub fn parse(text: &str) -> Result<T, String> {
+  let json = try!(Json::from_str(text).map_err(|err| format!("Invalid JSON: {}", err)));
+  let settings =
+    match json {
+      Json::Object(settings) => settings,
+      _ => return Err(String::from("The config should be a JSON object.")),
+    };
+
+  let mut config: T = Default::default();
+  for (key, value) in &settings {
+    let key = key.as_str();
+    match key {
+      "updates_per_second" => config.updates_per_second = try!(integer(key, value, 1, 1000)),
+      "sun_step" => config.sun_step = try!(integer(key, value, 0, 65535)) as u16,
+      "seed" => config.seed = try!(integer(key, value, 0, u32::max_value() as u64)) as u32,
+      "kill_plane" => config.kill_plane = try!(number(key, value, -1e6, 0.0)),
+      "client_timeout_secs" => config.client_timeout_secs = try!(integer(key, value, 1, 3600)),
+      "player_load_distance" => config.player_load_distance = try!(integer(key, value, 1, 32)) as i32,
+      "max_jump_fuel" => config.max_jump_fuel = try!(integer(key, value, 0, 1000)) as u32,
+      "max_step_height" => config.max_step_height = try!(number(key, value, 0.0, 16.0)),
+      "voxel_memory_budget_mib" => config.voxel_memory_budget_mib = try!(integer(key, value, 16, 1 << 20)) as usize,
+      "world_radius" => config.world_radius = try!(number(key, value, 1024.0, 262144.0)),
+      "spawn_search_top" => config.spawn_search_top = try!(number(key, value, -4096.0, 4096.0)),
+      "spawn_search_bottom" => config.spawn_search_bottom = try!(number(key, value, -4096.0, 4096.0)),
+      "spawn_search_radius" => config.spawn_search_radius = try!(number(key, value, 0.0, 64.0)),
+      "max_mob_loaded_blocks" => config.max_mob_loaded_blocks = try!(integer(key, value, 0, 1 << 20)) as usize,
+      "max_brush_extent" => config.max_brush_extent = try!(number(key, value, 1.0, 64.0)),
+      "max_reach" => config.max_reach = try!(number(key, value, 1.0, 1024.0)),
+      "aim_tolerance_degrees" => config.aim_tolerance_degrees = try!(number(key, value, 0.0, 180.0)),
+      "max_undo_steps" => config.max_undo_steps = try!(integer(key, value, 0, 1000)) as usize,
+      "world_spawn" => {
+        let xz =
+          match value.as_array() {
+            Some(xz) if xz.len() == 2 => xz,
+            _ => return Err(format!("world_spawn should be an [x, z] pair, not {}.", value)),
+          };
+        config.world_spawn = Point2::new(try!(number(key, &xz[0], -1e6, 1e6)), try!(number(key, &xz[1], -1e6, 1e6)));
+      },
+      "world_file" => {
+        config.world_file =
+          match value.as_string() {
+            Some(path) if !path.is_empty() => String::from(path),
+            _ => return Err(format!("world_file should be a path, not {}.", value)),
+          };
+      },
+      _ => return Err(format!("Unknown setting {:?}.", key)),
+    }
+  }
+
+  // Checks between settings, once they've all been read.
+  let spawn = config.world_spawn;
+  if spawn.x.abs() > config.world_radius || spawn.y.abs() > config.world_radius {
+    return Err(format!("world_spawn {:?} should be within world_radius ({}) of the origin.", spawn, config.world_radius))
+  }
+  if config.spawn_search_bottom >= config.spawn_search_top {
+    return Err(String::from("spawn_search_bottom should be below spawn_search_top."))
+  }
+  Ok(config)
+}
+
+/// Read settings from the file at `path`.
+pub fn load(path: &str) -> Result<T, String> {
+  let mut text = String::new();
+  let mut file = try!(File::open(path).map_err(|err| format!("Couldn't open {}: {}", path, err)));
+  try!(file.read_to_string(&mut text).map_err(|err| format!("Couldn't read {}: {}", path, err)));
+  parse(&text).map_err(|err| format!("In {}: {}", path, err))
+}
+
+#[test]
+fn missing_settings_are_defaulted() {
+  let config = parse("{ \"updates_per_second\": 60, \"player_load_distance\": 4 }").unwrap();
+  assert_eq!(config.updates_per_second, 60);
+  assert_eq!(config.player_load_distance, 4);
+  assert_eq!(config.world_file, T::default().world_file);
+
+  assert!(parse("{ \"updates_per_second\": 0 }").is_err());
+  assert!(parse("{ \"seed\": -1 }").is_err());
+  assert!(parse("{ \"world_file\": 3 }").is_err());
+  assert!(parse("{ \"aim_tolerance_degrees\": 270 }").is_err());
+  assert!(parse("{ \"world_spawn\": [0, 4, 0] }").is_err());
+  assert!(parse("{ \"world_spawn\": [2000, 0], \"world_radius\": 1024 }").is_err());
+  assert!(parse("{ \"spawn_search_top\": -200 }").is_err());
+  assert!(parse("{ \"updates_per_scond\": 60 }").is_err());
+  assert!(parse("[]").is_err());
+}
@@ -0,0 +1,84 @@
+//! A player's undo and redo stacks for their edits to the terrain.
+
+use std::collections::VecDeque;
+
+use common::voxel;
+
+/// Voxels to write back into the terrain, e.g. the ones a brush overwrote.
+pub type Edit = Vec<(voxel::bounds::T, voxel::T)>;
+
+/// Edits that can be undone, and undone edits that can be redone.
+pub struct T {
+  undo: VecDeque<Edit>,
+  redo: VecDeque<Edit>,
+  max_steps: usize,
+}
+
+/// An empty history that remembers up to `max_steps` edits in each direction.
+pub fn new(max_steps: usize) -> T {
+  T {
+    undo: VecDeque::new(),
+    redo: VecDeque::new(),
+    max_steps: max_steps,
+  }
+}
+
+fn push_bounded(stack: &mut VecDeque<Edit>, edit: Edit, max_steps: usize) {
+  if max_steps == 0 {
+    return
+  }
+  if stack.len() >= max_steps {
+    stack.pop_front();
+  }
+  stack.push_back(edit);
+}
+
+impl T {
+  /// Remember what a new edit overwrote. Anything that was undone can't be redone anymore.
+  pub fn record(&mut self, overwritten: Edit) {
+    self.redo.clear();
+    push_bounded(&mut self.undo, overwritten, self.max_steps);
+  }
+
+  /// Undo the last edit. `apply` writes an edit into the terrain, and returns what it overwrote.
+  /// Returns false if there's nothing to undo.
+  pub fn undo<Apply>(&mut self, apply: Apply) -> bool where Apply: FnOnce(Edit) -> Edit {
+    match self.undo.pop_back() {
+      None => false,
+      Some(edit) => {
+        let overwritten = apply(edit);
+        push_bounded(&mut self.redo, overwritten, self.max_steps);
+        true
+      },
+    }
+  }
+
+  /// Redo the last undone edit. See `undo`.
+  pub fn redo<Apply>(&mut self, apply: Apply) -> bool where Apply: FnOnce(Edit) -> Edit {
+    match self.redo.pop_back() {
+      None => false,
+      Some(edit) => {
+        let overwritten = apply(edit);
+        push_bounded(&mut self.undo, overwritten, self.max_steps);
+        true
+      },
+    }
+  }
+}
+
+#[test]
+fn undo_and_redo_are_bounded() {
+  let edit = |x| vec!((voxel::bounds::new(x, 0, 0, 0), voxel::Volume(voxel::Material::Stone)));
+  let mut history = new(2);
+  history.record(edit(1));
+  history.record(edit(2));
+  history.record(edit(3));
+
+  let mut undone = Vec::new();
+  while history.undo(|e| { undone.push(e[0].0.x); edit(-e[0].0.x) }) {}
+  assert_eq!(undone, vec!(3, 2));
+
+  assert!(history.redo(|e| { assert_eq!(e[0].0.x, -2); e }));
+  history.record(edit(4));
+  assert!(!history.redo(|e| e));
+}
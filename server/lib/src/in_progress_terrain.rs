@@ -1,6 +1,6 @@
 use cgmath::{Aabb3};
 use std::collections::hash_map::{HashMap, Entry};
-use std::sync::Mutex;
+use std::cell::RefCell;
 
 use common::entity_id;
 use common::id_allocator;
@@ -23,8 +23,8 @@ impl T {
   /// Mark a block as in-progress by making it solid.
   pub fn insert(
     &mut self,
-    id_allocator: &Mutex<id_allocator::T<entity_id::T>>,
-    physics: &Mutex<Physics>,
+    id_allocator: &RefCell<id_allocator::T<entity_id::T>>,
+    physics: &RefCell<Physics>,
     block_position: &voxel::bounds::T,
   ) -> bool {
     match self.blocks.entry(*block_position) {
@@ -33,11 +33,11 @@ impl T {
         false
       },
       Entry::Vacant(entry) => {
-        let id = id_allocator::allocate(id_allocator);
+        let id = id_allocator.borrow_mut().allocate();
         entry.insert(id);
 
         let (low, high) = block_position.corners();
-        physics.lock().unwrap().insert_misc(id, &Aabb3::new(low, high));
+        physics.borrow_mut().insert_misc(id, &Aabb3::new(low, high));
         true
       }
     }
@@ -46,10 +46,10 @@ impl T {
   /// Unmark an in-progress block, either because loading is done, or the block was unloaded.
   pub fn remove(
     &mut self,
-    physics: &Mutex<Physics>,
+    physics: &RefCell<Physics>,
     block_position: &voxel::bounds::T,
   ) -> bool {
     self.blocks.remove(block_position)
-      .map(|id| physics.lock().unwrap().remove_misc(id)).is_some()
+      .map(|id| physics.borrow_mut().remove_misc(id)).is_some()
   }
 }
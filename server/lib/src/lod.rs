@@ -61,6 +61,11 @@ impl Map {
     })
   }
 
+  /// Every position that at least one owner holds.
+  pub fn positions(&self) -> Vec<voxel::bounds::T> {
+    self.loaded.keys().cloned().collect()
+  }
+
   // TODO: Can probably get rid of the LODChange returns; we only assert with em.
 
   /// Acquire/update an owner's handle in `position`.
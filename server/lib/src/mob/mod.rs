@@ -0,0 +1,351 @@
+//! Mobs, and the kinds of mob there are.
+
+use cgmath::{Aabb3, Point, Point2, Point3, Vector, Vector3};
+use std::collections::{BTreeMap, HashSet};
+
+use common::entity_id;
+use common::protocol;
+use common::voxel;
+
+use lod;
+use navigation;
+use server::Server;
+use spawn;
+use update_gaia;
+
+pub mod perception;
+pub mod tag;
+pub mod wanderer;
+
+/// How far around themselves (in blocks) active mobs keep the terrain fully loaded.
+const LOAD_RADIUS: i32 = 2;
+/// Mobs further than this (along x/z) from every player are frozen, and let go of their terrain.
+pub const ACTIVE_DISTANCE: f32 = 48.0;
+/// How much mobs' vertical speed drops each update.
+pub const GRAVITY: f32 = 0.1;
+
+/// What makes one kind of mob different from another.
+/// Any per-mob state (e.g. what it's currently doing) lives in the implementing type.
+pub trait Kind: Send {
+  /// A short name, for logging.
+  fn name(&self) -> &'static str;
+  /// The dimensions of the mob.
+  fn size(&self) -> Vector3<f32>;
+  /// The fastest the mob moves along x/z on its own, in world units per update.
+  fn max_speed(&self) -> f32;
+  /// How high the mob can jump. Paths are only planned up ledges it can step or jump onto.
+  fn jump_height(&self) -> f32 {
+    0.0
+  }
+  /// Decide how the mob moves this update, by changing `body.speed`.
+  fn update(&mut self, server: &Server, body: Body);
+}
+
+/// The parts of a mob its kind can look at and control.
+pub struct Body<'a> {
+  /// The center of the mob.
+  pub position: &'a Point3<f32>,
+  #[allow(missing_docs)]
+  pub speed: &'a mut Vector3<f32>,
+  /// For kinds that go somewhere in particular to plan their way there.
+  pub navigator: &'a mut navigation::Navigator,
+  /// What the mob has noticed of the players around it.
+  pub perception: &'a perception::T,
+}
+
+pub struct Mob {
+  pub position: Point3<f32>,
+  pub speed: Vector3<f32>,
+  /// The direction the mob is looking, along x/z.
+  pub facing: Vector3<f32>,
+  pub kind: Box<Kind>,
+  pub navigator: navigation::Navigator,
+  pub perception: perception::T,
+
+  pub entity_id: entity_id::T,
+  pub owner_id: lod::OwnerId,
+  /// The blocks this mob has asked to be fully loaded.
+  pub loaded_blocks: HashSet<Point3<i32>>,
+}
+
+fn block_of(position: &Point3<f32>) -> Point3<i32> {
+  Point3::new(position.x.floor() as i32, position.y.floor() as i32, position.z.floor() as i32)
+}
+
+impl Mob {
+  /// Fully load the blocks around the mob, requesting at most `budget` new ones,
+  /// and unload the ones it's left behind.
+  pub fn load_surroundings<RequestBlock>(
+    &mut self,
+    server: &Server,
+    request_block: &mut RequestBlock,
+    budget: usize,
+  ) where
+    RequestBlock: FnMut(update_gaia::Message),
+  {
+    let center = block_of(&self.position);
+    let is_near = |block: &Point3<i32>| {
+      (block.x - center.x).abs() <= LOAD_RADIUS &&
+      (block.y - center.y).abs() <= LOAD_RADIUS &&
+      (block.z - center.z).abs() <= LOAD_RADIUS
+    };
+
+    let stale: Vec<Point3<i32>> = self.loaded_blocks.iter().filter(|block| !is_near(block)).cloned().collect();
+    for block in stale {
+      server.terrain_loader.unload(&server.physics, &voxel::bounds::new(block.x, block.y, block.z, 0), self.owner_id);
+      self.loaded_blocks.remove(&block);
+    }
+
+    let mut budget = budget;
+    for x in center.x - LOAD_RADIUS .. center.x + LOAD_RADIUS + 1 {
+      for y in center.y - LOAD_RADIUS .. center.y + LOAD_RADIUS + 1 {
+        for z in center.z - LOAD_RADIUS .. center.z + LOAD_RADIUS + 1 {
+          if budget == 0 {
+            return
+          }
+          if self.loaded_blocks.insert(Point3::new(x, y, z)) {
+            server.terrain_loader.load(
+              &server.id_allocator,
+              &server.physics,
+              &voxel::bounds::new(x, y, z, 0),
+              lod::Full,
+              self.owner_id,
+              request_block,
+            );
+            budget -= 1;
+          }
+        }
+      }
+    }
+  }
+
+  /// Whether the mob is close enough to any of `players` (along x/z) to keep moving.
+  pub fn is_active(&self, players: &[Point3<f32>]) -> bool {
+    players.iter().any(|player| {
+      let (dx, dz) = (player.x - self.position.x, player.z - self.position.z);
+      dx*dx + dz*dz <= ACTIVE_DISTANCE * ACTIVE_DISTANCE
+    })
+  }
+
+  /// Let go of all the terrain this mob has loaded.
+  pub fn unload_surroundings(&mut self, server: &Server) {
+    for block in self.loaded_blocks.drain() {
+      server.terrain_loader.unload(&server.physics, &voxel::bounds::new(block.x, block.y, block.z, 0), self.owner_id);
+    }
+  }
+
+  /// Whether the terrain the mob could run into this update has finished loading.
+  /// Until it has, the mob would only be colliding with placeholders (or nothing).
+  pub fn is_terrain_loaded(&self, server: &Server) -> bool {
+    let center = block_of(&self.position);
+    let loaded = server.terrain_loader.loaded.borrow_mut();
+    for x in center.x - 1 .. center.x + 2 {
+      for y in center.y - 1 .. center.y + 2 {
+        for z in center.z - 1 .. center.z + 2 {
+          if !loaded.contains_key(&voxel::bounds::new(x, y, z, 0)) {
+            return false
+          }
+        }
+      }
+    }
+    true
+  }
+
+  /// Let the mob's kind decide where it's going, within its speed limit.
+  pub fn think(&mut self, server: &Server) {
+    self.perception.update(server, &self.position, &self.facing);
+    self.kind.update(
+      server,
+      Body {
+        position: &self.position,
+        speed: &mut self.speed,
+        navigator: &mut self.navigator,
+        perception: &self.perception,
+      },
+    );
+
+    let max_speed = self.kind.max_speed();
+    let lateral_speed = (self.speed.x * self.speed.x + self.speed.z * self.speed.z).sqrt();
+    if lateral_speed > max_speed {
+      let scale = max_speed / lateral_speed;
+      self.speed.x = self.speed.x * scale;
+      self.speed.z = self.speed.z * scale;
+    }
+    if lateral_speed > 0.0 {
+      self.facing = Vector3::new(self.speed.x, 0.0, self.speed.z);
+    }
+  }
+}
+
+/// Load the terrain around the mobs that are active near `players`, and unload it around the rest.
+/// Between them, the mobs keep at most `max_mob_loaded_blocks` blocks loaded; once that runs out,
+/// the mobs updated later wait for others to let go of theirs.
+pub fn update_surroundings<RequestBlock>(
+  server: &Server,
+  mobs: &mut BTreeMap<entity_id::T, Mob>,
+  players: &[Point3<f32>],
+  request_block: &mut RequestBlock,
+) where
+  RequestBlock: FnMut(update_gaia::Message),
+{
+  let mut loaded_blocks = mobs.values().fold(0, |n, mob| n + mob.loaded_blocks.len());
+  for (_, mob) in mobs.iter_mut() {
+    let loaded_before = mob.loaded_blocks.len();
+    if mob.is_active(players) {
+      let budget = server.config.max_mob_loaded_blocks.saturating_sub(loaded_blocks);
+      mob.load_surroundings(server, request_block, budget);
+    } else {
+      mob.unload_surroundings(server);
+    }
+    loaded_blocks = loaded_blocks - loaded_before + mob.loaded_blocks.len();
+  }
+}
+
+/// A new mob with the given bounds. It isn't added to the world.
+fn new(server: &Server, kind: Box<Kind>, bounds: &Aabb3<f32>) -> Mob {
+  Mob {
+    position: bounds.min.add_v(&bounds.max.to_vec()).mul_s(0.5),
+    speed: Vector3::new(0.0, 0.0, 0.0),
+    facing: Vector3::new(1.0, 0.0, 0.0),
+    navigator: navigation::new(kind.size(), server.config.max_step_height.max(kind.jump_height())),
+    kind: kind,
+    perception: perception::new(),
+    entity_id: server.id_allocator.borrow_mut().allocate(),
+    owner_id: server.owner_allocator.borrow_mut().allocate(),
+    loaded_blocks: HashSet::new(),
+  }
+}
+
+/// Add a mob of some kind on the ground as close to `position` (in x/z) as possible.
+/// Returns None if there's nowhere to put it.
+pub fn spawn(
+  server: &Server,
+  kind: Box<Kind>,
+  position: &Point2<f32>,
+) -> Option<entity_id::T> {
+  let size = kind.size();
+  let low_corner =
+    match spawn::find(server, position, &size) {
+      None => return None,
+      Some(low_corner) => low_corner,
+    };
+  let bounds = Aabb3::new(low_corner, low_corner.add_v(&size));
+  let mob = new(server, kind, &bounds);
+  let entity_id = mob.entity_id;
+  debug!("Spawning {} {:?} at {:?}", mob.kind.name(), entity_id, low_corner);
+
+  server.physics.borrow_mut().insert_misc(entity_id, &bounds);
+  server.mobs.borrow_mut().insert(entity_id, mob);
+  Some(entity_id)
+}
+
+/// Remove a mob from the world, along with its hold on the terrain around it.
+pub fn despawn(server: &Server, id: entity_id::T) {
+  let mut mob =
+    match server.mobs.borrow_mut().remove(&id) {
+      None => return,
+      Some(mob) => mob,
+    };
+  debug!("Despawning {} {:?}", mob.kind.name(), id);
+
+  server.physics.borrow_mut().remove_misc(id);
+  mob.unload_surroundings(server);
+
+  for (_, client) in server.clients.borrow_mut().iter_mut() {
+    client.send(protocol::ServerToClient::RemoveMob(id));
+  }
+}
+
+#[cfg(test)]
+fn test_server() -> Server {
+  use std::sync::Arc;
+
+  use config;
+  use terrain;
+
+  let config =
+    config::T {
+      max_mob_loaded_blocks: 200,
+      world_file: String::from("/nonexistent/world.bin"),
+      ..Default::default()
+    };
+  Server::new(config, Arc::new(terrain::T::new(terrain::Seed::new(0))))
+}
+
+#[cfg(test)]
+fn test_mob(server: &Server, x: f32) -> Mob {
+  let bounds = Aabb3::new(Point3::new(x, 0.0, 0.0), Point3::new(x + 1.0, 1.0, 1.0));
+  new(server, Box::new(wanderer::new()), &bounds)
+}
+
+#[test]
+fn surroundings_follow_the_mob() {
+  let server = test_server();
+  let mut mob = test_mob(&server, 0.0);
+  let side = 2 * LOAD_RADIUS + 1;
+  let all = (side * side * side) as usize;
+
+  let mut requests = 0;
+  mob.load_surroundings(&server, &mut |_| requests += 1, 10);
+  assert_eq!(requests, 10);
+  mob.load_surroundings(&server, &mut |_| requests += 1, all);
+  // Blocks that were already loaded aren't asked for again.
+  assert_eq!(requests, all);
+  assert_eq!(mob.loaded_blocks.len(), all);
+
+  mob.position = Point3::new(10.5, 0.5, 0.5);
+  mob.load_surroundings(&server, &mut |_| {}, all);
+  assert_eq!(mob.loaded_blocks.len(), all);
+  assert!(mob.loaded_blocks.iter().all(|block| (block.x - 10).abs() <= LOAD_RADIUS));
+
+  mob.unload_surroundings(&server);
+  assert!(mob.loaded_blocks.is_empty());
+}
+
+#[test]
+fn mobs_share_a_loading_budget_and_freeze_away_from_players() {
+  let server = test_server();
+  let mut mobs = BTreeMap::new();
+  for &x in &[0.0, 20.0, 100.0] {
+    let mob = test_mob(&server, x);
+    mobs.insert(mob.entity_id, mob);
+  }
+  let ids: Vec<entity_id::T> = mobs.keys().cloned().collect();
+  let players = [Point3::new(10.0, 0.0, 0.0)];
+
+  update_surroundings(&server, &mut mobs, &players, &mut |_| {});
+  // The first mob loads all 125 blocks around itself, which leaves 75 for the second.
+  // The third is too far from the player to load anything.
+  assert_eq!(mobs[&ids[0]].loaded_blocks.len(), 125);
+  assert_eq!(mobs[&ids[1]].loaded_blocks.len(), 75);
+  assert!(!mobs[&ids[2]].is_active(&players));
+  assert!(mobs[&ids[2]].loaded_blocks.is_empty());
+
+  // Once the player walks off, the first two let go of their terrain, and the third takes over.
+  let players = [Point3::new(100.0, 0.0, 0.0)];
+  update_surroundings(&server, &mut mobs, &players, &mut |_| {});
+  assert!(mobs[&ids[0]].loaded_blocks.is_empty());
+  assert!(mobs[&ids[1]].loaded_blocks.is_empty());
+  assert_eq!(mobs[&ids[2]].loaded_blocks.len(), 125);
+}
+
+#[test]
+fn mobs_wait_for_their_terrain() {
+  let server = test_server();
+  let mut mob = test_mob(&server, 0.0);
+
+  let mut loads = Vec::new();
+  mob.load_surroundings(&server, &mut |load| loads.push(load), usize::max_value());
+  assert!(!mob.is_terrain_loaded(&server));
+
+  for load in loads {
+    match load {
+      update_gaia::Message::Load(bounds, reason) => {
+        let generated = update_gaia::generate(&server.terrain_loader.terrain, bounds, reason);
+        update_gaia::update_gaia(&server, generated, &mut |_, _| {});
+      },
+      _ => panic!("Mobs should only ask for terrain to be loaded."),
+    }
+  }
+  assert!(mob.is_terrain_loaded(&server));
+}
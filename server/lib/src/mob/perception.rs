@@ -0,0 +1,132 @@
+//! What mobs know about the players around them.
+
+use cgmath::{Point, Point3, Ray3, EuclideanVector, Vector, Vector3};
+use std::collections::BTreeMap;
+
+use common::entity_id;
+use common::voxel;
+
+use server::Server;
+
+/// How far mobs can see.
+const VIEW_DISTANCE: f32 = 32.0;
+/// The cosine of the angle between where a mob is facing and the edge of what it can see.
+const VIEW_CONE: f32 = 0.5;
+/// Mobs notice players this close regardless of where they're facing or what's in the way.
+const HEARING_RADIUS: f32 = 6.0;
+/// How long mobs remember where they last noticed a player, in updates.
+const MEMORY_TICKS: u32 = 300;
+
+/// What a mob knows about one player.
+#[derive(Debug, Clone, Copy)]
+pub struct Sighting {
+  #[allow(missing_docs)]
+  pub player: entity_id::T,
+  /// Where the player was when the mob last noticed them.
+  pub position: Point3<f32>,
+  /// How many updates ago that was; 0 means the mob can perceive them right now.
+  pub age: u32,
+}
+
+impl Sighting {
+  #[allow(missing_docs)]
+  pub fn is_current(&self) -> bool {
+    self.age == 0
+  }
+}
+
+/// A mob's perception of nearby players.
+pub struct T {
+  sightings: BTreeMap<entity_id::T, Sighting>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    sightings: BTreeMap::new(),
+  }
+}
+
+/// Whether nothing solid in the terrain blocks the straight line from `from` to `to`.
+pub fn line_of_sight(server: &Server, from: &Point3<f32>, to: &Point3<f32>) -> bool {
+  let v = to.sub_p(from);
+  let distance = v.length();
+  if distance == 0.0 {
+    return true
+  }
+  let ray = Ray3::new(*from, v.div_s(distance));
+
+  let blocked =
+    server.terrain_loader.terrain.voxels.cast_ray(
+      &ray,
+      &mut |bounds, voxel| {
+        let (low, high) = bounds.corners();
+        let along = low.add_v(&high.to_vec()).mul_s(0.5).sub_p(from).dot(&ray.direction);
+        if along > distance {
+          // Everything from here on is behind `to`.
+          Some(false)
+        } else {
+          match voxel {
+            &voxel::Volume(voxel::Material::Empty) => None,
+            _ => Some(true),
+          }
+        }
+      }
+    );
+  !blocked.unwrap_or(false)
+}
+
+impl T {
+  /// Look and listen for players from `position`, facing along `facing`.
+  pub fn update(&mut self, server: &Server, position: &Point3<f32>, facing: &Vector3<f32>) {
+    let mut forgotten = Vec::new();
+    for (&id, sighting) in self.sightings.iter_mut() {
+      sighting.age += 1;
+      if sighting.age > MEMORY_TICKS {
+        forgotten.push(id);
+      }
+    }
+    for id in forgotten {
+      self.sightings.remove(&id);
+    }
+
+    let players: Vec<(entity_id::T, Point3<f32>)> =
+      server.players.borrow_mut().iter()
+      .filter(|&(_, player)| player.respawn_ticks.is_none())
+      .map(|(&id, player)| (id, player.position))
+      .collect();
+
+    for (id, player) in players {
+      let to_player = player.sub_p(position);
+      let distance = to_player.length();
+      let perceived =
+        distance <= HEARING_RADIUS || (
+          distance <= VIEW_DISTANCE &&
+          to_player.dot(facing) >= VIEW_CONE * distance * facing.length() &&
+          line_of_sight(server, position, &player)
+        );
+      if perceived {
+        self.sightings.insert(id, Sighting { player: id, position: player, age: 0 });
+      }
+    }
+  }
+
+  /// What the mob knows about `player`, if anything.
+  pub fn sighting(&self, player: entity_id::T) -> Option<&Sighting> {
+    self.sightings.get(&player)
+  }
+
+  /// The closest player the mob currently perceives, or if there isn't one,
+  /// the most recently noticed one.
+  pub fn target(&self, position: &Point3<f32>) -> Option<&Sighting> {
+    let distance2 = |sighting: &Sighting| sighting.position.sub_p(position).length2();
+    self.sightings.values().fold(None, |best: Option<&Sighting>, sighting| {
+      match best {
+        Some(best) if
+          best.age < sighting.age ||
+          (best.age == sighting.age && distance2(best) <= distance2(sighting)) => Some(best),
+        _ => Some(sighting),
+      }
+    })
+  }
+}
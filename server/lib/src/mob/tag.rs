@@ -0,0 +1,117 @@
+//! A mob that plays tag: once you tag it, it chases you until it tags you back.
+
+use cgmath::{Point, Point3, EuclideanVector, Vector, Vector3};
+
+use common::entity_id;
+
+use mob;
+use mob::perception;
+use server::Server;
+
+enum State {
+  /// Waiting to be tagged.
+  Idle,
+  /// Tagged by a player; give them a head start.
+  WaitForDistance(entity_id::T),
+  /// Chasing the player, or wherever they were last noticed.
+  Follow(entity_id::T),
+  /// Tagged the player back; wait for them to leave.
+  WaitToReset(entity_id::T),
+}
+
+const RUN_SPEED: f32 = 0.5;
+const JUMP_HEIGHT: f32 = 1.5;
+
+pub struct T {
+  state: State,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    state: State::Idle,
+  }
+}
+
+fn chase(server: &Server, body: mob::Body, target: &Point3<f32>) {
+  match body.navigator.steer(&server.terrain_loader.terrain, body.position, target) {
+    None => {
+      // Still working out how to get there.
+      body.speed.x = 0.0;
+      body.speed.z = 0.0;
+    },
+    Some(direction) => {
+      let lateral = Vector3::new(direction.x, 0.0, direction.z);
+      if lateral.length2() > 0.0 {
+        let lateral = lateral.normalize().mul_s(RUN_SPEED);
+        body.speed.x = lateral.x;
+        body.speed.z = lateral.z;
+      }
+      // Jump up ledges too high to step onto, but only from the ground.
+      if direction.y > server.config.max_step_height && body.speed.y == 0.0 {
+        body.speed.y = (2.0 * mob::GRAVITY * (JUMP_HEIGHT + 0.5)).sqrt();
+      }
+    },
+  }
+}
+
+impl mob::Kind for T {
+  fn name(&self) -> &'static str {
+    "tag"
+  }
+
+  fn size(&self) -> Vector3<f32> {
+    Vector3::new(1.0, 2.0, 1.0)
+  }
+
+  fn max_speed(&self) -> f32 {
+    RUN_SPEED
+  }
+
+  fn jump_height(&self) -> f32 {
+    JUMP_HEIGHT
+  }
+
+  fn update(&mut self, server: &Server, body: mob::Body) {
+    let position = *body.position;
+    let distance = |sighting: &perception::Sighting| sighting.position.sub_p(&position).length();
+
+    self.state =
+      match self.state {
+        State::Idle => {
+          match body.perception.target(&position) {
+            Some(sighting) if sighting.is_current() && distance(sighting) < 2.0 =>
+              State::WaitForDistance(sighting.player),
+            _ => State::Idle,
+          }
+        },
+        State::WaitForDistance(player) => {
+          match body.perception.sighting(player) {
+            None => State::Idle,
+            Some(sighting) if distance(sighting) > 8.0 => State::Follow(player),
+            Some(_) => State::WaitForDistance(player),
+          }
+        },
+        State::Follow(player) => {
+          match body.perception.sighting(player).cloned() {
+            // Lost track of them.
+            None => State::Idle,
+            Some(ref sighting) if sighting.is_current() && distance(sighting) < 2.0 => {
+              *body.speed = Vector3::new(0.0, 0.0, 0.0);
+              State::WaitToReset(player)
+            },
+            Some(sighting) => {
+              chase(server, body, &sighting.position);
+              State::Follow(player)
+            },
+          }
+        },
+        State::WaitToReset(player) => {
+          match body.perception.sighting(player) {
+            Some(sighting) if sighting.is_current() && distance(sighting) < 2.0 => State::WaitToReset(player),
+            _ => State::Idle,
+          }
+        },
+      };
+  }
+}
@@ -0,0 +1,61 @@
+//! A passive mob that ambles around aimlessly.
+
+use cgmath::Vector3;
+use rand::Rng;
+use std::f32::consts::PI;
+
+use mob;
+use server::Server;
+
+const WALK_SPEED: f32 = 0.1;
+/// The shortest and longest a wanderer keeps going one way (or standing still).
+const MIN_TICKS: u32 = 30;
+const MAX_TICKS: u32 = 150;
+/// The chance of standing still instead of picking a new direction.
+const IDLE_CHANCE: f32 = 0.3;
+
+pub struct T {
+  direction: Vector3<f32>,
+  ticks_left: u32,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    direction: Vector3::new(0.0, 0.0, 0.0),
+    ticks_left: 0,
+  }
+}
+
+impl mob::Kind for T {
+  fn name(&self) -> &'static str {
+    "wanderer"
+  }
+
+  fn size(&self) -> Vector3<f32> {
+    Vector3::new(0.8, 0.8, 0.8)
+  }
+
+  fn max_speed(&self) -> f32 {
+    WALK_SPEED
+  }
+
+  fn update(&mut self, server: &Server, body: mob::Body) {
+    if self.ticks_left == 0 {
+      let mut rng = server.rng.borrow_mut();
+      self.direction =
+        if rng.gen::<f32>() < IDLE_CHANCE {
+          Vector3::new(0.0, 0.0, 0.0)
+        } else {
+          let (s, c) = rng.gen_range(0.0, 2.0 * PI).sin_cos();
+          Vector3::new(c, 0.0, s)
+        };
+      self.ticks_left = rng.gen_range(MIN_TICKS, MAX_TICKS);
+    } else {
+      self.ticks_left -= 1;
+    }
+
+    body.speed.x = self.direction.x * WALK_SPEED;
+    body.speed.z = self.direction.z * WALK_SPEED;
+  }
+}
@@ -0,0 +1,84 @@
+//! Rules for when and where mobs appear and disappear.
+
+use cgmath::{Point2, Point3};
+use rand::Rng;
+use std::f32::consts::PI;
+
+use common::entity_id;
+
+use mob;
+use server::Server;
+
+/// Never have more than this many mobs in the world.
+const MAX_MOBS: usize = 32;
+/// Try to keep this many mobs within `SPAWN_RADIUS` of each player.
+const MOBS_PER_PLAYER: usize = 4;
+/// Mobs are spawned at most this far (along x/z) from a player.
+const SPAWN_RADIUS: f32 = 32.0;
+/// Mobs are spawned at least this far from a player, so they don't pop into existence in front of them.
+const MIN_SPAWN_DISTANCE: f32 = 12.0;
+/// Mobs this far from every player are removed.
+const DESPAWN_DISTANCE: f32 = 96.0;
+/// The fraction of spawned mobs that play tag; the rest wander.
+const TAG_CHANCE: f32 = 0.25;
+
+fn distance2(p1: &Point3<f32>, p2: &Point3<f32>) -> f32 {
+  let (dx, dz) = (p1.x - p2.x, p1.z - p2.z);
+  dx*dx + dz*dz
+}
+
+/// Despawn mobs that have been left behind, and spawn new ones near players who need them.
+pub fn update(server: &Server) {
+  let players: Vec<Point3<f32>> =
+    server.players.borrow_mut().values()
+    .filter(|player| player.respawn_ticks.is_none())
+    .map(|player| player.position)
+    .collect();
+  let mobs: Vec<(entity_id::T, Point3<f32>)> =
+    server.mobs.borrow_mut().iter()
+    .map(|(&id, mob)| (id, mob.position))
+    .collect();
+
+  let mut mob_count = mobs.len();
+  for &(id, ref position) in &mobs {
+    let abandoned =
+      players.iter()
+      .all(|player| distance2(player, position) > DESPAWN_DISTANCE * DESPAWN_DISTANCE);
+    if abandoned {
+      mob::despawn(server, id);
+      mob_count -= 1;
+    }
+  }
+
+  for player in &players {
+    if mob_count >= MAX_MOBS {
+      break
+    }
+
+    let nearby =
+      mobs.iter()
+      .filter(|&&(_, ref position)| distance2(player, position) <= SPAWN_RADIUS * SPAWN_RADIUS)
+      .count();
+    if nearby >= MOBS_PER_PLAYER {
+      continue
+    }
+
+    let (position, kind) = {
+      let mut rng = server.rng.borrow_mut();
+      let (s, c) = rng.gen_range(0.0, 2.0 * PI).sin_cos();
+      let distance = rng.gen_range(MIN_SPAWN_DISTANCE, SPAWN_RADIUS);
+      let position = Point2::new(player.x + c * distance, player.z + s * distance);
+      let kind: Box<mob::Kind> =
+        if rng.gen::<f32>() < TAG_CHANCE {
+          Box::new(mob::tag::new())
+        } else {
+          Box::new(mob::wanderer::new())
+        };
+      (position, kind)
+    };
+
+    if mob::spawn(server, kind, &position).is_some() {
+      mob_count += 1;
+    }
+  }
+}
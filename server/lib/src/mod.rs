@@ -35,19 +35,34 @@ extern crate thread_scoped;
 extern crate time;
 extern crate voxel_data;
 
+pub mod admin;
+mod aabb_tree;
+mod brush;
 mod client_recv_thread;
+pub mod config;
+mod history;
 mod in_progress_terrain;
-mod init_mobs;
 mod lod;
 mod mob;
+mod mob_spawner;
+mod navigation;
+// Only kept as a baseline for the broad-phase benchmarks.
+#[cfg(test)]
+#[allow(dead_code)]
 mod octree;
 mod physics;
 mod player;
+mod queue;
 mod run;
+mod schematic;
 mod server;
+mod spawn;
 mod sun;
+mod terrain_collision;
 mod terrain_loader;
+mod tick;
 mod update_gaia;
 mod update_world;
+mod world_file;
 
 pub use run::run;
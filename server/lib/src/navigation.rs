@@ -0,0 +1,433 @@
+//! Plan and follow paths over the terrain.
+
+use cgmath::{Aabb3, Point, Point3, Vector, Vector3};
+use std::cmp::{min, max, Ordering};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+#[cfg(test)]
+use common::voxel;
+
+use terrain;
+use terrain_collision;
+
+/// The most cells a search expands in one update. Searches that need more pick up where they left off.
+pub const EXPANSIONS_PER_UPDATE: u32 = 32;
+/// Searches give up after expanding this many cells.
+const MAX_EXPANSIONS: u32 = 1024;
+/// The furthest a mob will drop off a ledge.
+const MAX_DROP: f32 = 3.0;
+/// A goal within this many cells (in each direction) of the one a path leads to is close enough.
+const GOAL_TOLERANCE: i32 = 2;
+/// Waypoints count as reached once the mob is this close to them along x/z.
+const WAYPOINT_RADIUS: f32 = 0.3;
+/// Mobs further than this from their next waypoint have been knocked off their path.
+const MAX_STRAY: f32 = 4.0;
+
+/// A place to stand: a unit column, and the (rounded-down) height of the mob's feet in it.
+type Cell = Point3<i32>;
+
+fn cell(x: i32, feet: f32, z: i32) -> Cell {
+  Point3::new(x, feet.floor() as i32, z)
+}
+
+fn near(c1: &Cell, c2: &Cell) -> bool {
+  (c1.x - c2.x).abs() <= GOAL_TOLERANCE &&
+  (c1.y - c2.y).abs() <= GOAL_TOLERANCE &&
+  (c1.z - c2.z).abs() <= GOAL_TOLERANCE
+}
+
+fn distance(c1: &Cell, c2: &Cell) -> f32 {
+  let (dx, dy, dz) = (c1.x - c2.x, c1.y - c2.y, c1.z - c2.z);
+  ((dx*dx + dy*dy + dz*dz) as f32).sqrt()
+}
+
+/// Whether the unit voxel at `v` is in `voxels`, whose high corner is exclusive.
+fn contains(voxels: &Aabb3<i32>, v: &Point3<i32>) -> bool {
+  voxels.min.x <= v.x && v.x < voxels.max.x &&
+  voxels.min.y <= v.y && v.y < voxels.max.y &&
+  voxels.min.z <= v.z && v.z < voxels.max.z
+}
+
+/// Whether two boxes of voxels, with exclusive high corners, share any voxels.
+fn overlaps(b1: &Aabb3<i32>, b2: &Aabb3<i32>) -> bool {
+  b1.min.x < b2.max.x && b2.min.x < b1.max.x &&
+  b1.min.y < b2.max.y && b2.min.y < b1.max.y &&
+  b1.min.z < b2.max.z && b2.min.z < b1.max.z
+}
+
+/// The walkable cells of the terrain, for a mob of a particular size.
+/// Voxels that haven't been generated are treated as solid, so searches don't generate terrain.
+struct Graph<'a> {
+  terrain: &'a terrain::T,
+  size: Vector3<f32>,
+  /// The highest ledge the mob can get up onto, by stepping or jumping.
+  max_climb: f32,
+}
+
+impl<'a> Graph<'a> {
+  /// The mob's bounds when it's centered in column `(x, z)` with its feet at `feet`.
+  fn bounds_at(&self, x: i32, feet: f32, z: i32) -> Aabb3<f32> {
+    let low = Point3::new(x as f32 + 0.5 - self.size.x / 2.0, feet, z as f32 + 0.5 - self.size.z / 2.0);
+    Aabb3::new(low, low.add_v(&self.size))
+  }
+
+  fn is_clear(&self, bounds: &Aabb3<f32>) -> bool {
+    terrain_collision::boxes_in(self.terrain, bounds).is_empty()
+  }
+
+  /// Drop the mob down column `(x, z)` from `feet`, by at most `max_drop`.
+  /// Returns the height its feet land at.
+  fn land(&self, x: i32, z: i32, feet: f32, max_drop: f32) -> Option<f32> {
+    let mut y = feet;
+    while y > feet - max_drop {
+      let hits = terrain_collision::boxes_in(self.terrain, &self.bounds_at(x, y - 1.0, z));
+      if !hits.is_empty() {
+        // The mob was clear at `y`, so everything it hit is below `y`.
+        return Some(hits.iter().fold(y - 1.0, |top, bounds| top.max(bounds.max.y)))
+      }
+      y -= 1.0;
+    }
+    None
+  }
+
+  /// The cells the mob can get to directly from standing at `from` with its feet at `feet`,
+  /// along with the height of its feet in each.
+  fn neighbors(&self, from: &Cell, feet: f32) -> Vec<(Cell, f32)> {
+    let top = feet + self.max_climb;
+    if !self.is_clear(&self.bounds_at(from.x, top, from.z)) {
+      // No headroom to climb; only consider moving to cells at this height or lower.
+      return self.neighbors_from(from, feet, feet)
+    }
+    self.neighbors_from(from, feet, top)
+  }
+
+  fn neighbors_from(&self, from: &Cell, feet: f32, top: f32) -> Vec<(Cell, f32)> {
+    let mut neighbors = Vec::new();
+    for &(dx, dz) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
+      let (x, z) = (from.x + dx, from.z + dz);
+      if !self.is_clear(&self.bounds_at(x, top, z)) {
+        continue
+      }
+      if let Some(landing) = self.land(x, z, top, top - feet + MAX_DROP) {
+        neighbors.push((cell(x, landing, z), landing));
+      }
+    }
+    neighbors
+  }
+}
+
+/// An entry in the open set, ordered so the `BinaryHeap` pops the lowest estimate first.
+#[derive(PartialEq)]
+struct Open {
+  estimate: f32,
+  cell: Cell,
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+  fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Open {
+  fn cmp(&self, other: &Open) -> Ordering {
+    other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+  }
+}
+
+/// A planned path: where to put the mob's feet, one cell at a time.
+struct Path {
+  waypoints: VecDeque<Point3<f32>>,
+  goal: Cell,
+  /// The unit voxels the path was planned through and over.
+  voxels: HashSet<Point3<i32>>,
+}
+
+impl Path {
+  fn crosses(&self, voxels: &Aabb3<i32>) -> bool {
+    self.voxels.iter().any(|v| contains(voxels, v))
+  }
+}
+
+enum Progress {
+  Searching,
+  Found(Path),
+  Failed,
+}
+
+/// An A* search, which can be spread over several updates.
+struct Search {
+  goal: Cell,
+  open: BinaryHeap<Open>,
+  /// For each cell reached: the cost to get there, the height of the mob's feet in it,
+  /// and the cell it was reached from.
+  visited: HashMap<Cell, (f32, f32, Option<Cell>)>,
+  expansions: u32,
+  /// The smallest box containing every cell expanded so far (and the start), including its high corner.
+  expanded: Aabb3<i32>,
+}
+
+impl Search {
+  fn new(start: Cell, feet: f32, goal: Cell) -> Search {
+    let mut open = BinaryHeap::new();
+    open.push(Open { estimate: distance(&start, &goal), cell: start });
+    let mut visited = HashMap::new();
+    visited.insert(start, (0.0, feet, None));
+    Search {
+      goal: goal,
+      open: open,
+      visited: visited,
+      expansions: 0,
+      expanded: Aabb3::new(start, start),
+    }
+  }
+
+  fn step(&mut self, graph: &Graph, budget: u32) -> Progress {
+    for _ in 0 .. budget {
+      let Open { estimate, cell } =
+        match self.open.pop() {
+          None => return Progress::Failed,
+          Some(open) => open,
+        };
+      let (cost, feet, _) = self.visited[&cell];
+      if estimate > cost + distance(&cell, &self.goal) {
+        // A cheaper way to this cell was found after this entry was pushed.
+        continue
+      }
+
+      if near(&cell, &self.goal) {
+        return Progress::Found(self.path(graph, cell))
+      }
+
+      self.expansions += 1;
+      if self.expansions > MAX_EXPANSIONS {
+        return Progress::Failed
+      }
+      self.expanded =
+        Aabb3::new(
+          Point3::new(min(self.expanded.min.x, cell.x), min(self.expanded.min.y, cell.y), min(self.expanded.min.z, cell.z)),
+          Point3::new(max(self.expanded.max.x, cell.x), max(self.expanded.max.y, cell.y), max(self.expanded.max.z, cell.z)),
+        );
+
+      for (next, next_feet) in graph.neighbors(&cell, feet) {
+        // Climbing costs extra, so flat detours win over small hills.
+        let next_cost = cost + distance(&cell, &next) + (next_feet - feet).max(0.0);
+        let better = self.visited.get(&next).map_or(true, |&(c, _, _)| next_cost < c);
+        if better {
+          self.visited.insert(next, (next_cost, next_feet, Some(cell)));
+          self.open.push(Open { estimate: next_cost + distance(&next, &self.goal), cell: next });
+        }
+      }
+    }
+
+    Progress::Searching
+  }
+
+  fn path(&self, graph: &Graph, end: Cell) -> Path {
+    let mut waypoints = VecDeque::new();
+    let mut voxels = HashSet::new();
+    let mut cell = Some(end);
+    while let Some(c) = cell {
+      let (_, feet, parent) = self.visited[&c];
+      waypoints.push_front(Point3::new(c.x as f32 + 0.5, feet, c.z as f32 + 0.5));
+
+      // The space the mob passes through, and the ground under it.
+      let bounds = graph.bounds_at(c.x, feet - 1.0, c.z);
+      for x in bounds.min.x.floor() as i32 .. bounds.max.x.ceil() as i32 {
+        for y in bounds.min.y.floor() as i32 .. (bounds.max.y + 1.0 + graph.max_climb).ceil() as i32 {
+          for z in bounds.min.z.floor() as i32 .. bounds.max.z.ceil() as i32 {
+            voxels.insert(Point3::new(x, y, z));
+          }
+        }
+      }
+
+      cell = parent;
+    }
+
+    Path {
+      waypoints: waypoints,
+      goal: self.goal,
+      voxels: voxels,
+    }
+  }
+}
+
+/// Plans paths for one mob, and steers it along them.
+pub struct Navigator {
+  size: Vector3<f32>,
+  max_climb: f32,
+  search: Option<Search>,
+  path: Option<Path>,
+  /// The goal of the last search that failed, and the cells it expanded.
+  /// It isn't retried until the goal moves, or the terrain it looked at changes.
+  failed: Option<(Cell, Aabb3<i32>)>,
+}
+
+/// A navigator for a mob of `size` that can step or jump up to `max_climb` high.
+pub fn new(size: Vector3<f32>, max_climb: f32) -> Navigator {
+  Navigator {
+    size: size,
+    max_climb: max_climb,
+    search: None,
+    path: None,
+    failed: None,
+  }
+}
+
+impl Navigator {
+  /// Work out which way a mob centered at `position` should head to get to `goal`.
+  /// Returns the vector from the mob's feet to its next waypoint, or None while it's still
+  /// planning. If there's no path to `goal`, this heads straight for it.
+  pub fn steer(
+    &mut self,
+    terrain: &terrain::T,
+    position: &Point3<f32>,
+    goal: &Point3<f32>,
+  ) -> Option<Vector3<f32>> {
+    let feet = position.y - self.size.y / 2.0;
+    let goal_cell = Point3::new(goal.x.floor() as i32, goal.y.floor() as i32, goal.z.floor() as i32);
+
+    if self.path.as_ref().map_or(false, |path| !near(&path.goal, &goal_cell)) {
+      self.path = None;
+    }
+    if self.failed.map_or(false, |(failed, _)| !near(&failed, &goal_cell)) {
+      self.failed = None;
+    }
+    if self.failed.is_some() {
+      return Some(goal.sub_p(position))
+    }
+
+    if self.path.is_none() {
+      let restart = self.search.as_ref().map_or(true, |search| !near(&search.goal, &goal_cell));
+      if restart {
+        let start = cell(position.x.floor() as i32, feet, position.z.floor() as i32);
+        self.search = Some(Search::new(start, feet, goal_cell));
+      }
+
+      let graph =
+        Graph {
+          terrain: terrain,
+          size: self.size,
+          max_climb: self.max_climb,
+        };
+      match self.search.as_mut().unwrap().step(&graph, EXPANSIONS_PER_UPDATE) {
+        Progress::Searching => return None,
+        Progress::Failed => {
+          let expanded = self.search.take().unwrap().expanded;
+          self.failed = Some((goal_cell, expanded));
+          return Some(goal.sub_p(position))
+        },
+        Progress::Found(path) => {
+          self.search = None;
+          self.path = Some(path);
+        },
+      }
+    }
+
+    let to_next = {
+      let path = self.path.as_mut().unwrap();
+      while let Some(&next) = path.waypoints.front() {
+        let (dx, dz) = (next.x - position.x, next.z - position.z);
+        if dx*dx + dz*dz < WAYPOINT_RADIUS * WAYPOINT_RADIUS && (next.y - feet).abs() < 1.0 {
+          path.waypoints.pop_front();
+        } else {
+          break
+        }
+      }
+      path.waypoints.front().map(|next| Vector3::new(next.x - position.x, next.y - feet, next.z - position.z))
+    };
+
+    match to_next {
+      // At the end of the path; the goal is close enough to head straight for.
+      None => Some(goal.sub_p(position)),
+      Some(to_next) => {
+        if to_next.length2() > MAX_STRAY * MAX_STRAY {
+          self.path = None;
+          None
+        } else {
+          Some(to_next)
+        }
+      },
+    }
+  }
+
+  /// Forget anything planned through `changed`, a box of voxels (with an exclusive high corner)
+  /// that has just been edited.
+  pub fn terrain_changed(&mut self, changed: &Aabb3<i32>) {
+    if self.path.as_ref().map_or(false, |path| path.crosses(changed)) {
+      self.path = None;
+    }
+    // Searches in progress (and failed ones) may have been looking at the old terrain.
+    if self.search.as_ref().map_or(false, |search| overlaps(&self.reach(&search.expanded), changed)) {
+      self.search = None;
+    }
+    if self.failed.map_or(false, |(_, expanded)| overlaps(&self.reach(&expanded), changed)) {
+      self.failed = None;
+    }
+  }
+
+  /// The voxels (with an exclusive high corner) that could have been looked at while expanding
+  /// the cells in `expanded`: the neighboring columns, from as far down as the mob can drop to
+  /// as far up as it can reach.
+  fn reach(&self, expanded: &Aabb3<i32>) -> Aabb3<i32> {
+    let side = (self.size.x.max(self.size.z) / 2.0).ceil() as i32 + 1;
+    let below = MAX_DROP.ceil() as i32 + 2;
+    let above = (self.max_climb + self.size.y).ceil() as i32 + 2;
+    Aabb3::new(
+      Point3::new(expanded.min.x - side, expanded.min.y - below, expanded.min.z - side),
+      Point3::new(expanded.max.x + side + 1, expanded.max.y + above, expanded.max.z + side + 1),
+    )
+  }
+}
+
+#[test]
+fn paths_go_around_walls() {
+  // A floor with a wall across most of it, too high to jump.
+  let terrain = terrain::T::new(terrain::Seed::new(0));
+  for x in 0 .. 12 {
+    for y in -1 .. 5 {
+      for z in 0 .. 12 {
+        let material =
+          if y < 0 || (x == 5 && z < 8 && y < 3) {
+            voxel::Material::Stone
+          } else {
+            voxel::Material::Empty
+          };
+        terrain.insert(&voxel::bounds::new(x, y, z, 0), voxel::Volume(material));
+      }
+    }
+  }
+
+  let graph =
+    Graph {
+      terrain: &terrain,
+      size: Vector3::new(0.8, 1.8, 0.8),
+      max_climb: 1.5,
+    };
+  let mut search = Search::new(Point3::new(2, 0, 2), 0.0, Point3::new(10, 0, 2));
+  let path =
+    match search.step(&graph, 1000) {
+      Progress::Found(path) => path,
+      _ => panic!("no path found"),
+    };
+
+  assert!(path.waypoints.iter().all(|p| p.y == 0.0));
+  assert!(path.waypoints.iter().any(|p| p.z > 8.0));
+  assert!(path.crosses(&Aabb3::new(Point3::new(5, 0, 9), Point3::new(6, 1, 10))));
+  assert!(!path.crosses(&Aabb3::new(Point3::new(11, 0, 0), Point3::new(12, 1, 1))));
+}
+
+#[test]
+fn only_nearby_edits_restart_searches() {
+  let mut navigator = new(Vector3::new(0.8, 1.8, 0.8), 1.5);
+  navigator.search = Some(Search::new(Point3::new(2, 0, 2), 0.0, Point3::new(10, 0, 2)));
+
+  navigator.terrain_changed(&Aabb3::new(Point3::new(100, 0, 100), Point3::new(101, 1, 101)));
+  assert!(navigator.search.is_some());
+
+  // The ground next to where the search started.
+  navigator.terrain_changed(&Aabb3::new(Point3::new(3, -1, 2), Point3::new(4, 0, 3)));
+  assert!(navigator.search.is_none());
+}
@@ -215,33 +215,25 @@ impl<V: Debug + Copy + Eq + PartialOrd> Octree<V> {
     }
   }
 
-  // Find whether there are objects overlapping the object & bounds provided in
-  // this/child trees. Uses equality comparison on V to ignore "same" objects.
-  // Returns the value associated with the first object intersected.
-  pub fn intersect(&self, bounds: &Aabb3<f32>, self_v: Option<V>) -> Option<(Aabb3<f32>, V)> {
+  // Call `f` on every object overlapping the bounds provided in this/child trees,
+  // except for those equal to `self_v`. Objects that were split between cells
+  // can be found more than once, with the bounds of each piece.
+  pub fn intersect_all<F>(&self, bounds: &Aabb3<f32>, self_v: Option<V>, f: &mut F)
+    where F: FnMut(&Aabb3<f32>, V)
+  {
     match self.contents {
       OctreeContents::Leaf(ref vs) => {
-        vs.iter()
-          .find(|&&(ref bs, ref v)| Some(*v) != self_v && aabb_overlap(bounds, bs))
-          .map(|&(bounds, v)| (bounds, v))
+        for &(ref bs, v) in vs {
+          if Some(v) != self_v && aabb_overlap(bounds, bs) {
+            f(bs, v);
+          }
+        }
       },
       OctreeContents::Branch(ref b) => {
         let mid = middle(&self.bounds, self.dimension);
         let (low_bounds, high_bounds) = split(mid, self.dimension, bounds);
-        let high = |high_bounds| {
-          match high_bounds {
-            None => None,
-            Some(bs) => b.high_tree.intersect(&bs, self_v),
-          }
-        };
-        match low_bounds {
-          None => high(high_bounds),
-          Some(bs) =>
-            match b.low_tree.intersect(&bs, self_v) {
-              None => high(high_bounds),
-              r => r,
-            }
-        }
+        low_bounds.map(|bs| b.low_tree.intersect_all(&bs, self_v, f));
+        high_bounds.map(|bs| b.high_tree.intersect_all(&bs, self_v, f));
       },
     }
   }
@@ -1,83 +1,393 @@
-use cgmath::{Aabb3, Point, Vector, Vector3};
-use octree::Octree;
+use cgmath::{Aabb3, Point, Point3, Vector, Vector3};
+use aabb_tree;
 use common::entity_id;
 use std::collections::HashMap;
+use std::f32;
+
+/// The default for the tallest ledge an object can step up onto without jumping.
+/// The server uses `config::T::max_step_height`.
+pub const MAX_STEP_HEIGHT: f32 = 1.0;
+/// The most times a single movement gets re-swept after stepping or sliding.
+const MAX_SWEEPS: u32 = 4;
+
+/// The first thing hit by a box swept through the world.
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+  /// The fraction of the movement completed before the hit, in [0, 1].
+  pub toi: f32,
+  /// The normal of the face hit, pointing out of the object hit.
+  pub normal: Vector3<f32>,
+  /// The bounds of the object hit.
+  pub bounds: Aabb3<f32>,
+}
+
+/// The result of moving an object through the world.
+pub struct Movement {
+  /// How far the object actually moved.
+  pub delta: Vector3<f32>,
+  /// The normals of the faces hit along the way.
+  pub normals: Vec<Vector3<f32>>,
+}
+
+fn translate(bounds: &Aabb3<f32>, v: &Vector3<f32>) -> Aabb3<f32> {
+  Aabb3::new(bounds.min.add_v(v), bounds.max.add_v(v))
+}
+
+/// Sweep `bounds` along `v` towards `obstacle`.
+/// Returns the time of impact in [0, 1] and the normal of the face hit.
+/// Boxes that only touch, or that already overlap, don't collide.
+pub fn sweep_aabb(
+  bounds: &Aabb3<f32>,
+  v: &Vector3<f32>,
+  obstacle: &Aabb3<f32>,
+) -> Option<(f32, Vector3<f32>)> {
+  let mut entry = -f32::INFINITY;
+  let mut exit = f32::INFINITY;
+  let mut normal = Vector3::new(0.0, 0.0, 0.0);
+
+  macro_rules! sweep_axis(($d:ident, $axis:expr) => {{
+    let (t_entry, t_exit, n) =
+      if v.$d > 0.0 {
+        (
+          (obstacle.min.$d - bounds.max.$d) / v.$d,
+          (obstacle.max.$d - bounds.min.$d) / v.$d,
+          $axis.mul_s(-1.0),
+        )
+      } else if v.$d < 0.0 {
+        (
+          (obstacle.max.$d - bounds.min.$d) / v.$d,
+          (obstacle.min.$d - bounds.max.$d) / v.$d,
+          $axis,
+        )
+      } else if bounds.max.$d <= obstacle.min.$d || obstacle.max.$d <= bounds.min.$d {
+        return None
+      } else {
+        (-f32::INFINITY, f32::INFINITY, Vector3::new(0.0, 0.0, 0.0))
+      };
+    if t_entry > entry {
+      entry = t_entry;
+      normal = n;
+    }
+    exit = exit.min(t_exit);
+  }});
+
+  sweep_axis!(x, Vector3::new(1.0, 0.0, 0.0));
+  sweep_axis!(y, Vector3::new(0.0, 1.0, 0.0));
+  sweep_axis!(z, Vector3::new(0.0, 0.0, 1.0));
+
+  if entry >= exit || entry < 0.0 || entry > 1.0 {
+    None
+  } else {
+    Some((entry, normal))
+  }
+}
 
 pub struct Physics {
-  pub terrain_octree: Octree<entity_id::T>,
-  pub misc_octree: Octree<entity_id::T>,
+  pub terrain_tree: aabb_tree::T<entity_id::T>,
+  pub misc_tree: aabb_tree::T<entity_id::T>,
   pub bounds: HashMap<entity_id::T, Aabb3<f32>>,
+  /// Moving objects are walled in to within this distance of the origin along x and z.
+  pub world_radius: f32,
 }
 
 impl Physics {
-  pub fn new(world_bounds: Aabb3<f32>) -> Physics {
+  pub fn new(world_radius: f32) -> Physics {
     Physics {
-      terrain_octree: Octree::new(&world_bounds),
-      misc_octree: Octree::new(&world_bounds),
+      terrain_tree: aabb_tree::T::new(),
+      misc_tree: aabb_tree::T::new(),
       bounds: HashMap::new(),
+      world_radius: world_radius,
     }
   }
 
   pub fn insert_terrain(&mut self, id: entity_id::T, bounds: &Aabb3<f32>) {
-    self.terrain_octree.insert(bounds, id);
+    self.terrain_tree.insert(bounds, id);
     self.bounds.insert(id, *bounds);
   }
 
   pub fn insert_misc(&mut self, id: entity_id::T, bounds: &Aabb3<f32>) {
-    self.misc_octree.insert(bounds, id);
+    self.misc_tree.insert(bounds, id);
     self.bounds.insert(id, *bounds);
   }
 
   pub fn remove_terrain(&mut self, id: entity_id::T) {
-    match self.bounds.get(&id) {
+    match self.bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.terrain_octree.remove(bounds, id);
+        self.terrain_tree.remove(&bounds, id);
       },
     }
   }
 
   pub fn remove_misc(&mut self, id: entity_id::T) {
-    match self.bounds.get(&id) {
+    match self.bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.misc_octree.remove(bounds, id);
+        self.misc_tree.remove(&bounds, id);
       },
     }
   }
 
+  /// Move an object in the misc tree straight to `bounds`, ignoring anything in the way.
+  pub fn teleport_misc(&mut self, id: entity_id::T, bounds: &Aabb3<f32>) {
+    self.remove_misc(id);
+    self.insert_misc(id, bounds);
+  }
+
   pub fn get_bounds(&self, id: entity_id::T) -> Option<&Aabb3<f32>> {
     self.bounds.get(&id)
   }
 
-  pub fn reinsert(
-    octree: &mut Octree<entity_id::T>,
+  /// Find the first thing (other than `id`) hit by sweeping `bounds` along `v`.
+  pub fn sweep(&self, id: entity_id::T, bounds: &Aabb3<f32>, v: &Vector3<f32>) -> Option<Contact> {
+    let moved = translate(bounds, v);
+    let swept =
+      Aabb3::new(
+        Point3::new(bounds.min.x.min(moved.min.x), bounds.min.y.min(moved.min.y), bounds.min.z.min(moved.min.z)),
+        Point3::new(bounds.max.x.max(moved.max.x), bounds.max.y.max(moved.max.y), bounds.max.z.max(moved.max.z)),
+      );
+
+    let mut obstacles = Vec::new();
+    self.terrain_tree.intersect_all(&swept, Some(id), &mut |bounds, _| obstacles.push(*bounds));
+    self.misc_tree.intersect_all(&swept, Some(id), &mut |bounds, _| obstacles.push(*bounds));
+
+    let mut first: Option<Contact> = None;
+    for obstacle in obstacles {
+      if let Some((toi, normal)) = sweep_aabb(bounds, v, &obstacle) {
+        if first.map_or(true, |first| toi < first.toi) {
+          first =
+            Some(Contact {
+              toi: toi,
+              normal: normal,
+              bounds: obstacle,
+            });
+        }
+      }
+    }
+    first
+  }
+
+  /// Move an object in the misc tree by up to `v`, sliding along whatever it hits.
+  /// Ledges up to `max_step_height` above the bottom of the object are stepped up onto.
+  pub fn move_misc(&mut self, id: entity_id::T, v: Vector3<f32>, max_step_height: f32) -> Movement {
+    let init_bounds = self.bounds[&id];
+    let mut bounds = init_bounds;
+    let mut v = v;
+    let mut normals = Vec::new();
+
+    for _ in 0 .. MAX_SWEEPS {
+      if v.x == 0.0 && v.y == 0.0 && v.z == 0.0 {
+        break
+      }
+
+      match self.sweep(id, &bounds, &v) {
+        None => {
+          bounds = translate(&bounds, &v);
+          break
+        },
+        Some(contact) => {
+          if contact.normal.y == 0.0 {
+            if let Some(stepped) = self.step_up(id, &bounds, &v, &contact, max_step_height) {
+              bounds = stepped;
+              continue
+            }
+          }
+
+          bounds = advance(&bounds, &v, &contact);
+          // Slide along the face we hit with whatever movement is left.
+          let remaining = v.mul_s(1.0 - contact.toi);
+          v = remaining.sub_v(&contact.normal.mul_s(remaining.dot(&contact.normal)));
+          normals.push(contact.normal);
+        },
+      }
+    }
+
+    // The world border is a wall. Objects already past it can still move back in.
+    let r = self.world_radius;
+    macro_rules! border(($d:ident, $axis:expr) => {{
+      if bounds.max.$d > r && bounds.max.$d > init_bounds.max.$d {
+        let back = bounds.max.$d - r.max(init_bounds.max.$d);
+        bounds.min.$d -= back;
+        bounds.max.$d -= back;
+        normals.push($axis.mul_s(-1.0));
+      } else if bounds.min.$d < -r && bounds.min.$d < init_bounds.min.$d {
+        let back = (-r).min(init_bounds.min.$d) - bounds.min.$d;
+        bounds.min.$d += back;
+        bounds.max.$d += back;
+        normals.push($axis);
+      }
+    }});
+    border!(x, Vector3::new(1.0, 0.0, 0.0));
+    border!(z, Vector3::new(0.0, 0.0, 1.0));
+
+    self.misc_tree.reinsert(id, &init_bounds, &bounds);
+    self.bounds.insert(id, bounds);
+
+    Movement {
+      delta: bounds.min.sub_p(&init_bounds.min),
+      normals: normals,
+    }
+  }
+
+  /// Try to lift `bounds` onto the top of the obstacle in `contact`.
+  fn step_up(
+    &self,
     id: entity_id::T,
-    bounds: &mut Aabb3<f32>,
-    new_bounds: &Aabb3<f32>,
-  ) -> Option<(Aabb3<f32>, entity_id::T)> {
-    match octree.intersect(new_bounds, Some(id)) {
-      None => {
-        octree.reinsert(id, bounds, new_bounds);
-        *bounds = *new_bounds;
-        None
-      },
-      collision => collision,
+    bounds: &Aabb3<f32>,
+    v: &Vector3<f32>,
+    contact: &Contact,
+    max_step_height: f32,
+  ) -> Option<Aabb3<f32>> {
+    let height = contact.bounds.max.y - bounds.min.y;
+    if height <= 0.0 || height > max_step_height {
+      return None
+    }
+
+    let up = Vector3::new(0.0, height, 0.0);
+    if self.sweep(id, bounds, &up).is_some() {
+      // No headroom.
+      return None
+    }
+
+    let mut raised = translate(bounds, &up);
+    // Land exactly on top of the step.
+    raised.min.y = contact.bounds.max.y;
+    raised.max.y = raised.min.y + (bounds.max.y - bounds.min.y);
+    match self.sweep(id, &raised, v) {
+      Some(c) if c.toi <= contact.toi => None,
+      _ => Some(raised),
     }
   }
+}
 
-  pub fn translate_misc(&mut self, id: entity_id::T, amount: Vector3<f32>) -> Option<(Aabb3<f32>, entity_id::T)> {
-    let bounds = self.bounds.get_mut(&id).unwrap();
-    let new_bounds =
-      Aabb3::new(
-        bounds.min.add_v(&amount),
-        bounds.max.add_v(&amount),
-      );
-    let terrain_collision = self.terrain_octree.intersect(&new_bounds, None);
-    if terrain_collision.is_none() {
-      Physics::reinsert(&mut self.misc_octree, id, bounds, &new_bounds)
-    } else {
-      terrain_collision
+/// Move `bounds` up to the contact, snapping it flush against the face hit
+/// so that rounding error can't leave it overlapping.
+fn advance(bounds: &Aabb3<f32>, v: &Vector3<f32>, contact: &Contact) -> Aabb3<f32> {
+  let mut moved = translate(bounds, &v.mul_s(contact.toi));
+
+  macro_rules! snap(($d:ident) => {{
+    let width = bounds.max.$d - bounds.min.$d;
+    if contact.normal.$d > 0.0 {
+      moved.min.$d = contact.bounds.max.$d;
+      moved.max.$d = moved.min.$d + width;
+    } else if contact.normal.$d < 0.0 {
+      moved.max.$d = contact.bounds.min.$d;
+      moved.min.$d = moved.max.$d - width;
+    }
+  }});
+
+  snap!(x);
+  snap!(y);
+  snap!(z);
+
+  moved
+}
+
+#[cfg(test)]
+fn cube(x: f32, y: f32, z: f32) -> Aabb3<f32> {
+  Aabb3::new(Point3::new(x, y, z), Point3::new(x + 1.0, y + 1.0, z + 1.0))
+}
+
+#[cfg(test)]
+/// A world with a floor whose top is at y = 0, with a player-sized box in it.
+fn test_world(ids: &mut ::common::id_allocator::T<entity_id::T>, player: &Aabb3<f32>) -> (Physics, entity_id::T) {
+  let mut physics = Physics::new(64.0);
+  for x in -8 .. 8 {
+    for z in -8 .. 8 {
+      physics.insert_terrain(ids.allocate(), &cube(x as f32, -1.0, z as f32));
+    }
+  }
+  let id = ids.allocate();
+  physics.insert_misc(id, player);
+  (physics, id)
+}
+
+#[test]
+fn fast_falls_land_on_the_floor() {
+  let mut ids = ::common::id_allocator::new();
+  let player = Aabb3::new(Point3::new(0.0, 10.0, 0.0), Point3::new(1.0, 12.0, 1.0));
+  let (mut physics, id) = test_world(&mut ids, &player);
+
+  let movement = physics.move_misc(id, Vector3::new(0.0, -100.0, 0.0), MAX_STEP_HEIGHT);
+  assert_eq!(physics.bounds[&id].min.y, 0.0);
+  assert_eq!(movement.delta.y, -10.0);
+  assert_eq!(movement.normals, vec!(Vector3::new(0.0, 1.0, 0.0)));
+
+  // Standing still on the floor still reports contact with it.
+  let movement = physics.move_misc(id, Vector3::new(0.0, -0.1, 0.0), MAX_STEP_HEIGHT);
+  assert_eq!(movement.delta.y, 0.0);
+  assert_eq!(movement.normals, vec!(Vector3::new(0.0, 1.0, 0.0)));
+}
+
+#[test]
+fn walls_stop_and_slide() {
+  let mut ids = ::common::id_allocator::new();
+  let player = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0));
+  let (mut physics, id) = test_world(&mut ids, &player);
+  // Walls too tall to step onto, along x = 2 and z = 2.
+  for y in 0 .. 3 {
+    for i in -8 .. 2 {
+      physics.insert_terrain(ids.allocate(), &cube(2.0, y as f32, i as f32));
+      physics.insert_terrain(ids.allocate(), &cube(i as f32, y as f32, 2.0));
     }
+    physics.insert_terrain(ids.allocate(), &cube(2.0, y as f32, 2.0));
   }
+
+  // Slide along the x = 2 wall.
+  physics.move_misc(id, Vector3::new(5.0, 0.0, -3.0), MAX_STEP_HEIGHT);
+  let bounds = physics.bounds[&id];
+  assert_eq!(bounds.max.x, 2.0);
+  assert!((bounds.min.z + 3.0).abs() < 0.0001);
+
+  // Get stuck in the corner.
+  physics.move_misc(id, Vector3::new(5.0, 0.0, 10.0), MAX_STEP_HEIGHT);
+  let bounds = physics.bounds[&id];
+  assert_eq!(bounds.max.x, 2.0);
+  assert_eq!(bounds.max.z, 2.0);
+  assert_eq!(bounds.min.y, 0.0);
+}
+
+#[test]
+fn step_up_slopes() {
+  let mut ids = ::common::id_allocator::new();
+  let player = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0));
+  let (mut physics, id) = test_world(&mut ids, &player);
+  // A slope rising half a block per block, then a cliff.
+  for i in 1 .. 5 {
+    let top = 0.5 * i as f32;
+    physics.insert_terrain(
+      ids.allocate(),
+      &Aabb3::new(Point3::new(i as f32, 0.0, 0.0), Point3::new(i as f32 + 1.0, top, 1.0)),
+    );
+  }
+  physics.insert_terrain(
+    ids.allocate(),
+    &Aabb3::new(Point3::new(5.0, 0.0, 0.0), Point3::new(6.0, 4.0, 1.0)),
+  );
+
+  for _ in 0 .. 16 {
+    physics.move_misc(id, Vector3::new(0.5, 0.0, 0.0), MAX_STEP_HEIGHT);
+    physics.move_misc(id, Vector3::new(0.0, -0.1, 0.0), MAX_STEP_HEIGHT);
+  }
+
+  let bounds = physics.bounds[&id];
+  assert_eq!(bounds.max.x, 5.0);
+  assert_eq!(bounds.min.y, 2.0);
+}
+
+#[test]
+fn the_world_border_is_a_wall() {
+  let mut ids = ::common::id_allocator::new();
+  let player = Aabb3::new(Point3::new(62.0, 0.0, 0.0), Point3::new(63.0, 2.0, 1.0));
+  let (mut physics, id) = test_world(&mut ids, &player);
+
+  let movement = physics.move_misc(id, Vector3::new(5.0, 0.0, -1.0), MAX_STEP_HEIGHT);
+  assert_eq!(physics.bounds[&id].max.x, 64.0);
+  assert_eq!(physics.bounds[&id].min.z, -1.0);
+  assert_eq!(movement.normals, vec!(Vector3::new(-1.0, 0.0, 0.0)));
+
+  // Moving back in isn't blocked.
+  let movement = physics.move_misc(id, Vector3::new(-5.0, 0.0, 0.0), MAX_STEP_HEIGHT);
+  assert_eq!(physics.bounds[&id].max.x, 59.0);
+  assert!(movement.normals.is_empty());
 }
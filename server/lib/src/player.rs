@@ -1,8 +1,7 @@
 use cgmath;
-use cgmath::{Aabb3, Point, Point3, Matrix, Matrix3, Ray, Ray3, Vector, Vector3};
+use cgmath::{Point, Point3, Matrix, Matrix3, Vector, Vector3};
 use std::f32::consts::PI;
-use std::ops::DerefMut;
-use std::sync::Mutex;
+use std::cell::RefCell;
 use stopwatch;
 
 use common::entity_id;
@@ -10,14 +9,25 @@ use common::id_allocator;
 use common::surroundings_loader::{SurroundingsLoader, LoadType};
 use common::voxel;
 
+use history;
 use lod;
-use physics::Physics;
 use server::Server;
 use update_gaia;
 use update_world::load_placeholders;
 
-const MAX_JUMP_FUEL: u32 = 4;
-const MAX_STEP_HEIGHT: f32 = 1.0;
+/// Players spawn with this much health.
+pub const MAX_HEALTH: u32 = 100;
+/// How many updates a dead player waits before respawning.
+pub const RESPAWN_TICKS: u32 = 90;
+/// Players can land at up to this speed without getting hurt.
+const SAFE_FALL_SPEED: f32 = 1.0;
+/// Health lost for every unit of speed past `SAFE_FALL_SPEED`.
+const FALL_DAMAGE: f32 = 40.0;
+
+/// The block containing `position`. This rounds down, so blocks on either side of the origin are the same size.
+fn block_of(position: &Point3<f32>) -> Point3<i32> {
+  Point3::new(position.x.floor() as i32, position.y.floor() as i32, position.z.floor() as i32)
+}
 
 // TODO: Add ObservablePlayer struct as a subset.
 pub struct Player {
@@ -32,6 +42,10 @@ pub struct Player {
   pub jump_fuel: u32,
   // are we currently trying to jump? (e.g. holding the key).
   pub is_jumping: bool,
+  pub health: u32,
+  // while dead, the number of updates until we respawn. This stays at 0 while there's nowhere
+  // near the world spawn to put us, e.g. when we've just joined and the terrain hasn't loaded.
+  pub respawn_ticks: Option<u32>,
   pub entity_id: entity_id::T,
 
   // rotation around the y-axis, in radians
@@ -44,15 +58,22 @@ pub struct Player {
   // Nearby blocks should be made solid if they aren't loaded yet.
   solid_boundary: SurroundingsLoader,
   solid_owner: lod::OwnerId,
+  // How far around the player (in blocks) terrain is loaded.
+  load_distance: i32,
+
+  /// The player's edits to the terrain, for undo and redo.
+  pub history: history::T,
 }
 
 impl Player {
   pub fn new(
     entity_id: entity_id::T,
-    owner_allocator: &Mutex<id_allocator::T<lod::OwnerId>>,
+    owner_allocator: &RefCell<id_allocator::T<lod::OwnerId>>,
+    load_distance: i32,
+    max_undo_steps: usize,
   ) -> Player {
-    let surroundings_owner = id_allocator::allocate(owner_allocator);
-    let solid_owner = id_allocator::allocate(owner_allocator);
+    let surroundings_owner = owner_allocator.borrow_mut().allocate();
+    let solid_owner = owner_allocator.borrow_mut().allocate();
     Player {
       position: Point3::new(0.0, 0.0, 0.0),
       speed: Vector3::new(0.0, 0.0, 0.0),
@@ -60,83 +81,91 @@ impl Player {
       walk_accel: Vector3::new(0.0, 0.0, 0.0),
       jump_fuel: 0,
       is_jumping: false,
+      health: MAX_HEALTH,
+      respawn_ticks: None,
       entity_id: entity_id,
       lateral_rotation: 0.0,
       vertical_rotation: 0.0,
 
-      surroundings_loader: SurroundingsLoader::new(8, Vec::new()),
-      solid_boundary:  SurroundingsLoader::new(8, Vec::new()),
+      surroundings_loader: SurroundingsLoader::new(load_distance, Vec::new()),
+      solid_boundary:  SurroundingsLoader::new(load_distance, Vec::new()),
       surroundings_owner:  surroundings_owner,
       solid_owner: solid_owner,
+      load_distance: load_distance,
+
+      history: history::new(max_undo_steps),
     }
   }
 
-  /// Translates the player by a vector.
-  /// If the player collides with something with a small height jump, the player will shift upward.
-  /// Returns the actual amount moved by.
+  /// Move the player by `v`, sliding along whatever it hits and stepping up onto low ledges.
   pub fn translate(
     &mut self,
-    physics: &Mutex<Physics>,
+    server: &Server,
     v: Vector3<f32>,
   ) {
-    let mut physics = physics.lock().unwrap();
-    let physics = physics.deref_mut();
-    let bounds = physics.bounds.get_mut(&self.entity_id).unwrap();
-    let init_bounds =
-      Aabb3::new(
-        bounds.min.add_v(&v),
-        bounds.max.add_v(&v),
-      );
-    let mut new_bounds = init_bounds;
-    // The height of the player's "step".
-    let mut step_height = 0.0;
-    let mut collided = false;
-    {
-      loop {
-        match physics.terrain_octree.intersect(&new_bounds, None) {
-          None => {
-            if Physics::reinsert(&mut physics.misc_octree, self.entity_id, bounds, &new_bounds).is_some() {
-              collided = true;
-            } else {
-              self.position.add_self_v(&v);
-              self.position.add_self_v(&Vector3::new(0.0, step_height, 0.0));
-            }
-            break;
-          },
-          Some((collision_bounds, _)) => {
-            collided = true;
-            // Step to the top of whatever we hit.
-            step_height = collision_bounds.max.y - init_bounds.min.y;
-            assert!(step_height > 0.0);
-
-            if step_height > MAX_STEP_HEIGHT {
-              // Step is too big; we just ran into something.
-              break;
-            }
-
-            new_bounds =
-              Aabb3::new(
-                init_bounds.min.add_v(&Vector3::new(0.0, step_height, 0.0)),
-                init_bounds.max.add_v(&Vector3::new(0.0, step_height, 0.0)),
-              );
-          },
+    let movement =
+      server.physics.borrow_mut().move_misc(self.entity_id, v, server.config.max_step_height);
+    self.position.add_self_v(&movement.delta);
+
+    let mut on_ground = false;
+    for normal in &movement.normals {
+      on_ground = on_ground || normal.y > 0.0;
+      // Stop moving into whatever we hit.
+      let into = self.speed.dot(normal);
+      if into < 0.0 {
+        if normal.y > 0.0 {
+          self.land(-into);
         }
+        self.speed.sub_self_v(&normal.mul_s(into));
       }
     }
 
-    if collided {
-      if v.y < 0.0 {
-        self.jump_fuel = MAX_JUMP_FUEL;
-      }
+    if v.y < 0.0 {
+      self.jump_fuel = if on_ground { server.config.max_jump_fuel } else { 0 };
+    }
+  }
+
+  /// Take fall damage for landing at `speed`.
+  fn land(&mut self, speed: f32) {
+    if speed > SAFE_FALL_SPEED {
+      let damage = ((speed - SAFE_FALL_SPEED) * FALL_DAMAGE) as u32;
+      self.health = self.health.saturating_sub(damage);
+    }
+  }
+
+  /// Start the countdown to respawning.
+  pub fn die(&mut self) {
+    self.health = 0;
+    self.speed = Vector3::new(0.0, 0.0, 0.0);
+    self.is_jumping = false;
+    self.respawn_ticks = Some(RESPAWN_TICKS);
+  }
 
-      self.speed.add_self_v(&-v);
-    } else {
-      if v.y < 0.0 {
-        self.jump_fuel = 0;
+  /// Let go of all the terrain this player has loaded, e.g. because they're leaving.
+  pub fn unload_surroundings(&self, server: &Server) {
+    let center = block_of(&self.position);
+    let d = self.load_distance;
+    for x in center.x - d .. center.x + d + 1 {
+      for y in center.y - d .. center.y + d + 1 {
+        for z in center.z - d .. center.z + d + 1 {
+          let bounds = voxel::bounds::new(x, y, z, 0);
+          server.terrain_loader.unload(&server.physics, &bounds, self.surroundings_owner);
+          server.terrain_loader.unload(&server.physics, &bounds, self.solid_owner);
+        }
       }
     }
   }
 
+  /// Bring the player back to life, centered at `position`.
+  pub fn respawn(&mut self, position: Point3<f32>) {
+    self.position = position;
+    self.speed = Vector3::new(0.0, 0.0, 0.0);
+    self.accel = Vector3::new(0.0, -0.1, 0.0);
+    self.jump_fuel = 0;
+    self.health = MAX_HEALTH;
+    self.respawn_ticks = None;
+  }
+
   pub fn update<RequestBlock>(
     &mut self,
     server: &Server,
@@ -144,12 +173,7 @@ impl Player {
   ) where
     RequestBlock: FnMut(update_gaia::Message),
   {
-    let player_position =
-      Point3::new(
-        self.position.x as i32,
-        self.position.y as i32,
-        self.position.z as i32,
-      );
+    let player_position = block_of(&self.position);
 
     stopwatch::time("update.player.surroundings", || {
       let owner = self.surroundings_owner;
@@ -200,15 +224,7 @@ impl Player {
     }
 
     let delta_p = self.speed;
-    if delta_p.x != 0.0 {
-      self.translate(&server.physics, Vector3::new(delta_p.x, 0.0, 0.0));
-    }
-    if delta_p.y != 0.0 {
-      self.translate(&server.physics, Vector3::new(0.0, delta_p.y, 0.0));
-    }
-    if delta_p.z != 0.0 {
-      self.translate(&server.physics, Vector3::new(0.0, 0.0, delta_p.z));
-    }
+    self.translate(server, delta_p);
 
     let y_axis = Vector3::new(0.0, 1.0, 0.0);
     let walk_v =
@@ -261,8 +277,4 @@ impl Player {
 
     transform.mul_v(&forward_orig)
   }
-
-  pub fn forward_ray(&self) -> Ray3<f32> {
-    Ray::new(self.position, self.forward())
-  }
 }
@@ -0,0 +1,53 @@
+//! A queue that threads can block on until there's something in it.
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+#[allow(missing_docs)]
+pub struct T<X> {
+  items: Mutex<VecDeque<X>>,
+  nonempty: Condvar,
+}
+
+#[allow(missing_docs)]
+pub fn new<X>() -> T<X> {
+  T {
+    items: Mutex::new(VecDeque::new()),
+    nonempty: Condvar::new(),
+  }
+}
+
+impl<X> T<X> {
+  /// Add an item to the back of the queue, waking up one thread waiting for it.
+  pub fn push(&self, x: X) {
+    self.items.lock().unwrap().push_back(x);
+    self.nonempty.notify_one();
+  }
+
+  /// Take the item at the front of the queue, waiting up to `timeout` for one to be pushed.
+  pub fn pop(&self, timeout: Duration) -> Option<X> {
+    let mut items = self.items.lock().unwrap();
+    if items.is_empty() {
+      items = self.nonempty.wait_timeout(items, timeout).unwrap().0;
+    }
+    items.pop_front()
+  }
+}
+
+#[test]
+fn pop_waits_for_push() {
+  use std::thread;
+
+  let queue = ::std::sync::Arc::new(new());
+  let pusher = {
+    let queue = queue.clone();
+    thread::spawn(move || {
+      thread::sleep(Duration::from_millis(10));
+      queue.push(1);
+    })
+  };
+  assert_eq!(queue.pop(Duration::from_secs(10)), Some(1));
+  assert_eq!(queue.pop(Duration::from_millis(1)), None);
+  pusher.join().unwrap();
+}
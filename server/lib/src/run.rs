@@ -1,65 +1,196 @@
-use std;
+use std::cmp;
+use std::collections::BTreeMap;
 use std::convert::AsRef;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use bincode;
 use stopwatch;
 use thread_scoped;
 use time;
 
-use common::closure_series;
+use common::protocol;
 use common::socket::ReceiveSocket;
+use common::voxel;
 
+use admin;
 use client_recv_thread::apply_client_update;
+use config;
+use queue;
 use server::Server;
+use spawn;
+use terrain;
 use update_gaia;
 use update_gaia::update_gaia;
 use update_world::update_world;
+use world_file;
 
-#[allow(missing_docs)]
-pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
-  let gaia_updates = Mutex::new(std::collections::VecDeque::new());
+/// The number of threads generating terrain for load requests.
+const TERRAIN_WORKER_THREADS: u32 = 3;
+/// The longest any thread blocks without checking whether it should quit.
+const QUIT_CHECK_INTERVAL_MS: u64 = 100;
 
-  let listen_socket = ReceiveSocket::new(listen_url.as_ref(), None);
-  let listen_socket = Mutex::new(listen_socket);
+/// Messages to the simulation thread, which owns the world.
+enum Event {
+  /// A message from a client, and its size in bytes.
+  FromClient(protocol::ClientToServer, usize),
+  Gaia(update_gaia::Message),
+  /// Terrain generated by a worker, and the number of the load request it's for.
+  Generated(u64, update_gaia::Message),
+  Admin(admin::Request),
+}
+
+/// Run the server. The world is owned by a single simulation thread, which runs the world
+/// updates and applies everything the other threads send it: the network thread decodes client
+/// messages, the terrain workers generate terrain, and commands are passed on from `admin`.
+/// The voxel tree is the only state shared between threads.
+///
+/// Generated terrain is held back until the next world update, and applied in the order it was
+/// requested, so which worker finishes first doesn't matter. Which update it's applied before still
+/// depends on how long the workers take, so mobs (which wait for their terrain to load) can start
+/// moving a few updates earlier or later from one run to the next.
+pub fn run(listen_url: &str, quit_signal: &Mutex<bool>, admin: &admin::Inbox, config: config::T) {
+  info!("Config: {:#?}", config);
+
+  let events = queue::new();
+  let terrain_loads = queue::new();
+
+  let quit_check_interval = Duration::from_millis(QUIT_CHECK_INTERVAL_MS);
+  let listen_socket = ReceiveSocket::new(listen_url.as_ref(), Some(quit_check_interval));
 
-  let server = Server::new();
-  let server = &server;
+  let terrain = Arc::new(terrain::T::new(terrain::Seed::new(config.seed)));
 
   let mut threads = Vec::new();
 
   unsafe {
-    let server = &server;
-    let gaia_updates = &gaia_updates;
-    let listen_socket = &listen_socket;
+    let events = &events;
+    let terrain_loads = &terrain_loads;
+    let terrain = terrain.clone();
+    threads.push(thread_scoped::scoped(move || {
+      let server = Server::new(config, terrain);
+      let server = &server;
+      let mut to_gaia = |up| events.push(Event::Gaia(up));
+      spawn::load_world_spawn(server, &mut to_gaia);
+
+      // Load requests are numbered, so their results can be applied in the same order.
+      let mut requested_loads: u64 = 0;
+      let mut request_load = |bounds: Vec<voxel::bounds::T>, reason: update_gaia::LoadReason| {
+        terrain_loads.push((requested_loads, bounds, reason));
+        requested_loads += 1;
+      };
+      let mut generated = BTreeMap::new();
+      let mut applied_loads: u64 = 0;
+
+      while !*quit_signal.lock().unwrap() && server.shutdown.borrow().is_none() {
+        // Handle events until the next world update is due.
+        let now = time::precise_time_ns();
+        let deadline = server.tick.borrow().next_deadline();
+        if deadline > now {
+          let wait = cmp::min(nanoseconds(deadline - now), quit_check_interval);
+          match events.pop(wait) {
+            None => {},
+            Some(Event::FromClient(up, bytes)) => {
+              server.received.borrow_mut().add(bytes);
+              apply_client_update(server, &mut to_gaia, up);
+              continue
+            },
+            Some(Event::Gaia(up)) => {
+              update_gaia(server, up, &mut request_load);
+              continue
+            },
+            Some(Event::Generated(load, up)) => {
+              generated.insert(load, up);
+              continue
+            },
+            Some(Event::Admin(request)) => {
+              let result = admin::execute(server, &mut to_gaia, request.command);
+              // The requester might have stopped waiting.
+              let _ = request.reply.send(result);
+              continue
+            },
+          }
+        }
+
+        let ticks = server.tick.borrow_mut().due(time::precise_time_ns());
+        for tick in ticks {
+          // Apply any generated terrain that's next in line. Nothing's applied out of order.
+          while let Some(up) = generated.remove(&applied_loads) {
+            update_gaia(server, up, &mut request_load);
+            applied_loads += 1;
+          }
+          update_world(server, tick, &mut to_gaia);
+        }
+      }
+
+      // Stop the other threads, whatever stopped this one.
+      *quit_signal.lock().unwrap() = true;
+      let reason = server.shutdown.borrow_mut().take();
+      shut_down(server, events, reason.unwrap_or_else(|| String::from(admin::DEFAULT_QUIT_REASON)));
+
+      stopwatch::clone()
+    }));
+  }
+  unsafe {
+    let events = &events;
+    let mut listen_socket = listen_socket;
     threads.push(thread_scoped::scoped(move || {
-      closure_series::new(vec!(
-        quit_upon(&quit_signal),
-        consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        consider_gaia_update(&server, || { gaia_updates.lock().unwrap().pop_front() } ),
-      ))
-      .until_quit();
+      // Whether the last read failed. Errors tend to repeat, so they're only logged once, and
+      // reads are retried no faster than the quit signal is checked.
+      let mut failing = false;
+      while !*quit_signal.lock().unwrap() {
+        match listen_socket.read_or_timeout() {
+          Ok(None) => {},
+          Ok(Some(up)) => {
+            if failing {
+              info!("Reading from the listen socket again.");
+              failing = false;
+            }
+            let decoded = bincode::rustc_serialize::decode(up.as_ref()).unwrap();
+            events.push(Event::FromClient(decoded, up.len()));
+          },
+          Err(err) => {
+            if !failing {
+              warn!("Error reading from the listen socket: {:?}", err);
+              failing = true;
+            }
+            thread::sleep(quit_check_interval);
+          },
+        }
+      }
 
       stopwatch::clone()
     }));
   }
   unsafe {
-    let server = &server;
-    let gaia_updates = &gaia_updates;
-    let quit_signal = &quit_signal;
-    let listen_socket = &listen_socket;
+    let events = &events;
     threads.push(thread_scoped::scoped(move || {
-      closure_series::new(vec!(
-        quit_upon(&quit_signal),
-        consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-      ))
-      .until_quit();
+      while !*quit_signal.lock().unwrap() {
+        if let Some(request) = admin.requests.pop(quit_check_interval) {
+          events.push(Event::Admin(request));
+        }
+      }
 
       stopwatch::clone()
     }));
   }
 
+  for _ in 0 .. TERRAIN_WORKER_THREADS {
+    unsafe {
+      let events = &events;
+      let terrain_loads = &terrain_loads;
+      let terrain = terrain.clone();
+      threads.push(thread_scoped::scoped(move || {
+        while !*quit_signal.lock().unwrap() {
+          if let Some((load, bounds, reason)) = terrain_loads.pop(quit_check_interval) {
+            events.push(Event::Generated(load, update_gaia::generate(&terrain, bounds, reason)));
+          }
+        }
+
+        stopwatch::clone()
+      }));
+    }
+  }
+
   for thread in threads.into_iter() {
     let stopwatch = thread.join();
     stopwatch.print();
@@ -68,67 +199,34 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
   stopwatch::clone().print();
 }
 
-fn quit_upon(signal: &Mutex<bool>) -> closure_series::Closure {
-  box move || {
-    if *signal.lock().unwrap() {
-      closure_series::Quit
-    } else {
-      closure_series::Continue
+/// Finish applying the edits already on their way, tell clients the server's going away,
+/// and save the world. Nothing else from clients is handled.
+fn shut_down(server: &Server, events: &queue::T<Event>, reason: String) {
+  info!("Shutting down: {}", reason);
+
+  while let Some(event) = events.pop(Duration::from_millis(0)) {
+    match event {
+      Event::Gaia(up) => update_gaia(server, up, &mut |_, _| {}),
+      // Loads don't change what's saved.
+      Event::Generated(..) => {},
+      Event::FromClient(..) => {},
+      Event::Admin(request) => {
+        let _ = request.reply.send(String::from("The server is shutting down."));
+      },
     }
   }
-}
 
-fn consider_world_update<'a, ToGaia>(
-  server: &'a Server,
-  mut to_gaia: ToGaia,
-) -> closure_series::Closure<'a> where
-  ToGaia: FnMut(update_gaia::Message) + 'a,
-{
-  box move || {
-    if server.update_timer.lock().unwrap().update(time::precise_time_ns()) > 0 {
-      update_world(
-        server,
-        &mut to_gaia,
-      );
-      closure_series::Restart
-    } else {
-      closure_series::Continue
-    }
+  for (_, client) in server.clients.borrow_mut().iter_mut() {
+    client.send(protocol::ServerToClient::ShuttingDown { reason: reason.clone() });
   }
-}
 
-fn network_listen<'a, ToGaia>(
-  socket: &'a Mutex<ReceiveSocket>,
-  server: &'a Server,
-  mut to_gaia: ToGaia,
-) -> closure_series::Closure<'a> where
-  ToGaia: FnMut(update_gaia::Message) + 'a,
-{
-  box move || {
-    match socket.lock().unwrap().try_read() {
-      None => closure_series::Continue,
-      Some(up) => {
-        let up = bincode::rustc_serialize::decode(up.as_ref()).unwrap();
-        apply_client_update(server, &mut to_gaia, up);
-        closure_series::Restart
-      },
-    }
+  let path = &server.config.world_file;
+  match world_file::save(&server.edits.borrow(), path) {
+    Ok(()) => info!("Saved {} edited voxels to {}.", server.edits.borrow().len(), path),
+    Err(err) => error!("{}", err),
   }
 }
 
-fn consider_gaia_update<'a, Get>(
-  server: &'a Server,
-  mut get_update: Get,
-) -> closure_series::Closure<'a> where
-  Get: FnMut() -> Option<update_gaia::Message> + 'a,
-{
-  box move || {
-    match get_update() {
-      Some(up) => {
-        update_gaia(server, up);
-        closure_series::Restart
-      },
-      None => closure_series::Continue,
-    }
-  }
+fn nanoseconds(ns: u64) -> Duration {
+  Duration::new(ns / 1_000_000_000, (ns % 1_000_000_000) as u32)
 }
@@ -0,0 +1,326 @@
+//! Copies of boxes of terrain, which can be saved and pasted elsewhere.
+//!
+//! A schematic file is the bincode encoding (as in the world file) of the tuple
+//! `(FORMAT_VERSION, (size_x, size_y, size_z), voxels)`: a `u32`, three `i32`s, and a `Vec<voxel::T>`
+//! of the finest voxels in the box, in order of x, then y, then z (z varies fastest).
+
+use bincode;
+use bincode::SizeLimit;
+use cgmath::{Aabb3, Point, Point3, Vector3};
+use std::fs::File;
+use std::io::{Read, Write};
+
+use common::voxel;
+
+use brush;
+use terrain;
+
+/// Written at the start of schematic files, to change if their layout ever does.
+pub const FORMAT_VERSION: u32 = 1;
+/// The most voxels a schematic can hold.
+pub const MAX_VOXELS: i64 = 1 << 21;
+
+/// A box of voxels, with its low corner at the origin.
+pub struct T {
+  /// The number of voxels along each axis.
+  pub size: Vector3<i32>,
+  /// See the module docs for the order.
+  pub voxels: Vec<voxel::T>,
+}
+
+/// Copy the finest voxels in `bounds` out of the terrain. They all have to be loaded already;
+/// if they aren't, the first one that isn't is returned instead.
+pub fn copy(terrain: &terrain::T, bounds: &Aabb3<i32>) -> Result<T, voxel::bounds::T> {
+  let mut voxels = Vec::new();
+  for x in bounds.min.x .. bounds.max.x {
+    for y in bounds.min.y .. bounds.max.y {
+      for z in bounds.min.z .. bounds.max.z {
+        let bounds = voxel::bounds::new(x, y, z, 0);
+        match terrain.get(&bounds) {
+          None => return Err(bounds),
+          Some(voxel) => voxels.push(voxel),
+        }
+      }
+    }
+  }
+  Ok(T {
+    size: bounds.max.sub_p(&bounds.min),
+    voxels: voxels,
+  })
+}
+
+impl T {
+  /// The voxel at a position within the schematic.
+  fn get(&self, x: i32, y: i32, z: i32) -> Option<voxel::T> {
+    let inside =
+      0 <= x && x < self.size.x &&
+      0 <= y && y < self.size.y &&
+      0 <= z && z < self.size.z;
+    if inside {
+      Some(self.voxels[((x * self.size.y + y) * self.size.z + z) as usize])
+    } else {
+      None
+    }
+  }
+
+  /// Write the schematic to the file at `path`.
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    let contents = (FORMAT_VERSION, (self.size.x, self.size.y, self.size.z), &self.voxels);
+    let bytes =
+      try!(
+        bincode::rustc_serialize::encode(&contents, SizeLimit::Infinite)
+        .map_err(|err| format!("Couldn't encode the schematic: {:?}", err))
+      );
+    let write = || -> ::std::io::Result<()> {
+      let mut file = try!(File::create(path));
+      file.write_all(&bytes)
+    };
+    write().map_err(|err| format!("Couldn't write {}: {}", path, err))
+  }
+}
+
+/// Read the schematic saved at `path`.
+pub fn load(path: &str) -> Result<T, String> {
+  let mut bytes = Vec::new();
+  let mut file = try!(File::open(path).map_err(|err| format!("Couldn't open {}: {}", path, err)));
+  try!(file.read_to_end(&mut bytes).map_err(|err| format!("Couldn't read {}: {}", path, err)));
+
+  // Check the header before decoding the voxels, so a bad size can't ask for too much memory.
+  let (version, (x, y, z)): (u32, (i32, i32, i32)) =
+    try!(
+      bincode::rustc_serialize::decode(&bytes)
+      .map_err(|err| format!("{} isn't a valid schematic: {:?}", path, err))
+    );
+  if version != FORMAT_VERSION {
+    return Err(format!("{} is a version {} schematic; expected version {}.", path, version, FORMAT_VERSION))
+  }
+  let count = x as i64 * y as i64 * z as i64;
+  if x < 0 || y < 0 || z < 0 || count > MAX_VOXELS {
+    return Err(format!("{} has size {:?}; schematics can hold up to {} voxels.", path, (x, y, z), MAX_VOXELS))
+  }
+
+  let (_, _, voxels): (u32, (i32, i32, i32), Vec<voxel::T>) =
+    try!(
+      bincode::rustc_serialize::decode(&bytes)
+      .map_err(|err| format!("{} isn't a valid schematic: {:?}", path, err))
+    );
+  if count != voxels.len() as i64 {
+    return Err(format!("{} has {} voxels, which doesn't fit its size {:?}.", path, voxels.len(), (x, y, z)))
+  }
+  Ok(T {
+    size: Vector3::new(x, y, z),
+    voxels: voxels,
+  })
+}
+
+/// How a schematic is turned when it's pasted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orientation {
+  /// Mirror along x (before turning).
+  pub mirror: bool,
+  /// Quarter turns around y, each taking +x to -z.
+  pub quarter_turns: u8,
+}
+
+impl Orientation {
+  /// The size of a box of `size` once it's been turned.
+  pub fn turn_size(&self, size: &Vector3<i32>) -> Vector3<i32> {
+    if self.quarter_turns % 2 == 0 {
+      *size
+    } else {
+      Vector3::new(size.z, size.y, size.x)
+    }
+  }
+
+  /// Turn a point within a box of `size` with its low corner at the origin.
+  /// The turned box also has its low corner at the origin.
+  pub fn to_turned(&self, size: &Vector3<f32>, p: &Point3<f32>) -> Point3<f32> {
+    let (mut x, mut z) = (p.x, p.z);
+    let (mut w, mut d) = (size.x, size.z);
+    if self.mirror {
+      x = w - x;
+    }
+    for _ in 0 .. self.quarter_turns % 4 {
+      let turned = (z, w - x);
+      x = turned.0;
+      z = turned.1;
+      let turned = (d, w);
+      w = turned.0;
+      d = turned.1;
+    }
+    Point3::new(x, p.y, z)
+  }
+
+  /// The inverse of `to_turned`: where a point in the turned box came from.
+  pub fn to_unturned(&self, size: &Vector3<f32>, p: &Point3<f32>) -> Point3<f32> {
+    let (mut x, mut z) = (p.x, p.z);
+    let turns = self.quarter_turns % 4;
+    let (mut w, mut d) = if turns % 2 == 0 { (size.x, size.z) } else { (size.z, size.x) };
+    for _ in 0 .. turns {
+      let unturned = (d - z, x);
+      x = unturned.0;
+      z = unturned.1;
+      let unturned = (d, w);
+      w = unturned.0;
+      d = unturned.1;
+    }
+    if self.mirror {
+      x = size.x - x;
+    }
+    Point3::new(x, p.y, z)
+  }
+}
+
+/// The offsets checked for a material to fill in solid parts of surface voxels with.
+const MATERIAL_SAMPLES: [[i32; 3]; 7] = [
+  [0, 0, 0],
+  [1, 0, 0], [-1, 0, 0],
+  [0, 1, 0], [0, -1, 0],
+  [0, 0, 1], [0, 0, -1],
+];
+
+/// A schematic placed in the world.
+struct Paste {
+  schematic: T,
+  orientation: Orientation,
+  target: Point3<i32>,
+}
+
+impl Paste {
+  /// The position within the schematic that `p` is pasted over, and the voxel there.
+  fn voxel_at(&self, p: &Point3<f32>) -> (Point3<f32>, voxel::bounds::T, Option<voxel::T>) {
+    let size = Vector3::new(self.schematic.size.x as f32, self.schematic.size.y as f32, self.schematic.size.z as f32);
+    let target = Point3::new(self.target.x as f32, self.target.y as f32, self.target.z as f32);
+    let p = self.orientation.to_unturned(&size, &Point3::from_vec(&p.sub_p(&target)));
+    let (x, y, z) = (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+    (p, voxel::bounds::new(x, y, z, 0), self.schematic.get(x, y, z))
+  }
+}
+
+impl voxel::field::T for Paste {
+  fn density(&self, p: &Point3<f32>) -> f32 {
+    // Turning and mirroring don't change distances, so this can all be done in the schematic's frame.
+    let (p, bounds, voxel) = self.voxel_at(p);
+    brush::density_in(&bounds, voxel, &p)
+  }
+
+  fn normal(&self, p: &Point3<f32>) -> Vector3<f32> {
+    brush::normal_of(self, p)
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for Paste {
+  fn material(&self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let (p, bounds, voxel) = self.voxel_at(p);
+    if voxel.is_none() {
+      // Leave everything outside the schematic alone.
+      return None
+    }
+    if brush::density_in(&bounds, voxel, &p) < 0.0 {
+      return Some(voxel::Material::Empty)
+    }
+    let material =
+      MATERIAL_SAMPLES.iter()
+      .map(|d| brush::material_of(self.schematic.get(bounds.x + d[0], bounds.y + d[1], bounds.z + d[2])))
+      .find(|&material| material != voxel::Material::Empty)
+      .unwrap_or(voxel::Material::Stone);
+    Some(material)
+  }
+}
+
+/// Make a brush that pastes `schematic` with its (turned) low corner at `target`.
+pub fn paste(schematic: T, target: &Point3<i32>, orientation: Orientation) -> brush::T {
+  let size = orientation.turn_size(&schematic.size);
+  voxel::brush::T {
+    bounds: Aabb3::new(*target, target.add_v(&size)),
+    mosaic: Box::new(Paste {
+      schematic: schematic,
+      orientation: orientation,
+      target: *target,
+    }),
+    min_lg_size: 0,
+  }
+}
+
+#[test]
+fn turning_is_undone() {
+  let size = Vector3::new(3.0, 2.0, 5.0);
+  let p = Point3::new(0.5, 1.0, 4.0);
+  for &mirror in &[false, true] {
+    for quarter_turns in 0 .. 4 {
+      let orientation = Orientation { mirror: mirror, quarter_turns: quarter_turns };
+      let turned = orientation.to_turned(&size, &p);
+      let turned_size = orientation.turn_size(&Vector3::new(3, 2, 5));
+      assert!(turned.x >= 0.0 && turned.x <= turned_size.x as f32);
+      assert!(turned.z >= 0.0 && turned.z <= turned_size.z as f32);
+      let back = orientation.to_unturned(&size, &turned);
+      assert!((back.x - p.x).abs() < 1e-4 && (back.y - p.y).abs() < 1e-4 && (back.z - p.z).abs() < 1e-4);
+    }
+  }
+}
+
+#[cfg(test)]
+fn materials(voxels: &[voxel::T]) -> Vec<voxel::Material> {
+  voxels.iter().map(|&voxel| brush::material_of(Some(voxel))).collect()
+}
+
+#[test]
+fn copies_survive_saving() {
+  let terrain = terrain::T::new(terrain::Seed::new(0));
+  let materials_at = |x: i32, z: i32| if (x + z) % 2 == 0 { voxel::Material::Stone } else { voxel::Material::Dirt };
+  for x in 0 .. 3 {
+    for z in 0 .. 2 {
+      terrain.insert(&voxel::bounds::new(x, 0, z, 0), voxel::Volume(materials_at(x, z)));
+    }
+  }
+
+  let bounds = Aabb3::new(Point3::new(0, 0, 0), Point3::new(3, 1, 2));
+  let schematic = copy(&terrain, &bounds).unwrap();
+  let path = ::std::env::temp_dir().join("playform_schematic_test.bin");
+  let path = path.to_str().unwrap();
+  schematic.save(path).unwrap();
+  let loaded = load(path).unwrap();
+
+  assert_eq!(loaded.size, Vector3::new(3, 1, 2));
+  assert_eq!(materials(&loaded.voxels), materials(&schematic.voxels));
+  assert_eq!(brush::material_of(loaded.get(2, 0, 1)), materials_at(2, 1));
+
+  // Only loaded voxels are copied.
+  let bounds = Aabb3::new(Point3::new(0, 0, 0), Point3::new(4, 1, 2));
+  match copy(&terrain, &bounds) {
+    Err(bounds) => assert_eq!((bounds.x, bounds.y, bounds.z), (3, 0, 0)),
+    Ok(_) => panic!("unloaded voxels were copied"),
+  }
+
+  // Oversized schematics are rejected before their voxels are read.
+  let contents = (FORMAT_VERSION, (1 << 11, 1 << 11, 1), Vec::<voxel::T>::new());
+  let bytes = bincode::rustc_serialize::encode(&contents, SizeLimit::Infinite).unwrap();
+  File::create(path).unwrap().write_all(&bytes).unwrap();
+  assert!(load(path).is_err());
+}
+
+#[test]
+fn pastes_are_turned_into_place() {
+  use common::voxel::mosaic::T as Mosaic;
+
+  // Stone at x = 0, and dirt at x = 1.
+  let schematic = || {
+    T {
+      size: Vector3::new(2, 1, 1),
+      voxels: vec!(voxel::Volume(voxel::Material::Stone), voxel::Volume(voxel::Material::Dirt)),
+    }
+  };
+  let material_at = |brush: &brush::T, x, y, z| brush.mosaic.material(&Point3::new(x, y, z));
+
+  // A quarter turn takes +x to -z.
+  let brush = paste(schematic(), &Point3::new(10, 0, 10), Orientation { mirror: false, quarter_turns: 1 });
+  assert_eq!(brush.bounds, Aabb3::new(Point3::new(10, 0, 10), Point3::new(11, 1, 12)));
+  assert_eq!(material_at(&brush, 10.5, 0.5, 11.5), Some(voxel::Material::Stone));
+  assert_eq!(material_at(&brush, 10.5, 0.5, 10.5), Some(voxel::Material::Dirt));
+  assert_eq!(material_at(&brush, 11.5, 0.5, 10.5), None);
+
+  // Mirroring flips x.
+  let brush = paste(schematic(), &Point3::new(0, 0, 0), Orientation { mirror: true, quarter_turns: 0 });
+  assert_eq!(material_at(&brush, 0.5, 0.5, 0.5), Some(voxel::Material::Dirt));
+  assert_eq!(material_at(&brush, 1.5, 0.5, 0.5), Some(voxel::Material::Stone));
+}
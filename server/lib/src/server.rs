@@ -1,35 +1,67 @@
-use cgmath::{Aabb3, Point3};
 use rand;
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::default::Default;
+use std::cell::RefCell;
+use std::sync::Arc;
 use time;
 
 use common::protocol;
 use common::entity_id;
 use common::id_allocator;
-use common::interval_timer::IntervalTimer;
 use common::socket::SendSocket;
 
-use init_mobs::init_mobs;
+use config;
 use lod;
 use mob;
 use physics::Physics;
 use player::Player;
 use sun::Sun;
+use terrain;
 use terrain_loader;
+use tick;
+use world_file;
+
+/// Unused terrain is evicted (and stats printed) once every this many seconds of updates.
+pub const STATS_INTERVAL_SECONDS: u64 = 2;
+/// Mobs are spawned and despawned once every this many seconds of updates.
+pub const MOB_SPAWN_INTERVAL_SECONDS: u64 = 1;
+
+/// An amount of network traffic.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Traffic {
+  pub messages: u64,
+  pub bytes: u64,
+}
 
-const UPDATES_PER_SECOND: u64 = 30;
-const SUN_TICK_NS: u64 = 1600000;
+impl Traffic {
+  pub fn add(&mut self, bytes: usize) {
+    self.messages += 1;
+    self.bytes += bytes as u64;
+  }
+}
 
 pub struct Client {
   pub socket: SendSocket,
+  /// The players this client added.
+  pub players: Vec<entity_id::T>,
+  pub sent: Traffic,
 }
 
 impl Client {
+  #[allow(missing_docs)]
+  pub fn new(socket: SendSocket) -> Client {
+    Client {
+      socket: socket,
+      players: Vec::new(),
+      sent: Default::default(),
+    }
+  }
+
   pub fn send(&mut self, msg: protocol::ServerToClient) {
     use bincode::SizeLimit;
     use bincode::rustc_serialize::encode;
     let msg = encode(&msg, SizeLimit::Infinite).unwrap();
+    self.sent.add(msg.len());
     match self.socket.write(msg.as_ref()) {
       Ok(()) => {},
       Err(err) => warn!("Error sending to client: {:?}", err),
@@ -37,70 +69,88 @@ impl Client {
   }
 }
 
-// TODO: Audit for s/Mutex/RwLock.
+/// The state of the world. This is owned by the simulation thread; everything else talks to it
+/// through messages (see `run`). The `RefCell`s are only there so that different parts of the
+/// state can be borrowed independently; since nothing is shared between threads, there's no lock
+/// order to get wrong.
 pub struct Server {
-  pub players: Mutex<HashMap<entity_id::T, Player>>,
-  pub mobs: Mutex<HashMap<entity_id::T, mob::Mob>>,
+  pub config: config::T,
 
-  pub id_allocator: Mutex<id_allocator::T<entity_id::T>>,
-  pub owner_allocator: Mutex<id_allocator::T<lod::OwnerId>>,
-  pub client_allocator: Mutex<id_allocator::T<protocol::ClientId>>,
+  // Players and mobs are kept in order, so they're always updated in the same order.
+  pub players: RefCell<BTreeMap<entity_id::T, Player>>,
+  pub mobs: RefCell<BTreeMap<entity_id::T, mob::Mob>>,
 
-  pub physics: Mutex<Physics>,
-  pub terrain_loader: terrain_loader::T,
-  pub rng: Mutex<rand::StdRng>,
-
-  pub clients: Mutex<HashMap<protocol::ClientId, Client>>,
+  pub id_allocator: RefCell<id_allocator::T<entity_id::T>>,
+  pub owner_allocator: RefCell<id_allocator::T<lod::OwnerId>>,
+  /// Keeps the terrain around the world spawn loaded.
+  pub spawn_owner: lod::OwnerId,
+  pub client_allocator: RefCell<id_allocator::T<protocol::ClientId>>,
 
-  pub sun: Mutex<Sun>,
-  pub update_timer: Mutex<IntervalTimer>,
+  pub physics: RefCell<Physics>,
+  pub terrain_loader: terrain_loader::T,
+  pub rng: RefCell<rand::StdRng>,
+
+  pub clients: RefCell<HashMap<protocol::ClientId, Client>>,
+  /// Everything received from clients.
+  pub received: RefCell<Traffic>,
+
+  pub sun: RefCell<Sun>,
+  /// When to run world updates.
+  pub tick: RefCell<tick::T>,
+  /// The edits to save with the world.
+  pub edits: RefCell<world_file::Edits>,
+  /// Once this is set, the server shuts down, giving clients this reason.
+  pub shutdown: RefCell<Option<String>>,
 }
 
 impl Server {
-  #[allow(missing_docs)]
-  pub fn new() -> Server {
-    let world_width: u32 = 1 << 11;
-    let world_width = world_width as f32;
-    let physics =
-      Physics::new(
-        Aabb3::new(
-          Point3 { x: -world_width, y: -512.0, z: -world_width },
-          Point3 { x: world_width, y: 512.0, z: world_width },
-        )
-      );
+  /// Make a new world. `terrain` is shared with the threads that generate it.
+  pub fn new(config: config::T, terrain: Arc<terrain::T>) -> Server {
+    let physics = Physics::new(config.world_radius);
+
+    let edits =
+      match world_file::load(&terrain, &config.world_file) {
+        Ok(edits) => edits,
+        Err(err) => panic!("{}", err),
+      };
+    info!("Loaded {} edited voxels from {}.", edits.len(), config.world_file);
 
     let id_allocator = id_allocator::new();
-    let owner_allocator = Mutex::new(id_allocator::new());
+    let mut owner_allocator = id_allocator::new();
+    let spawn_owner = owner_allocator.allocate();
 
     let server = Server {
-      players: Mutex::new(HashMap::new()),
-      mobs: Mutex::new(HashMap::new()),
+      players: RefCell::new(BTreeMap::new()),
+      mobs: RefCell::new(BTreeMap::new()),
 
-      id_allocator: Mutex::new(id_allocator),
-      owner_allocator: owner_allocator,
-      client_allocator: Mutex::new(id_allocator::new()),
+      id_allocator: RefCell::new(id_allocator),
+      owner_allocator: RefCell::new(owner_allocator),
+      spawn_owner: spawn_owner,
+      client_allocator: RefCell::new(id_allocator::new()),
 
-      physics: Mutex::new(physics),
-      terrain_loader: terrain_loader::T::new(),
+      physics: RefCell::new(physics),
+      terrain_loader: terrain_loader::T::new(terrain),
       rng: {
-        let seed = [0];
+        let seed = [config.seed as usize];
         let seed: &[usize] = &seed;
-        Mutex::new(rand::SeedableRng::from_seed(seed))
+        RefCell::new(rand::SeedableRng::from_seed(seed))
       },
 
-      clients: Mutex::new(HashMap::new()),
-      sun: Mutex::new(Sun::new(SUN_TICK_NS)),
+      clients: RefCell::new(HashMap::new()),
+      received: RefCell::new(Default::default()),
+      sun: RefCell::new(Sun::new(config.sun_step)),
 
-      update_timer: {
+      tick: {
         let now = time::precise_time_ns();
         let nanoseconds_per_second = 1000000000;
-        Mutex::new(
-          IntervalTimer::new(nanoseconds_per_second / UPDATES_PER_SECOND, now)
-        )
-      }
+        RefCell::new(tick::new(nanoseconds_per_second / config.updates_per_second, now))
+      },
+      edits: RefCell::new(edits),
+      shutdown: RefCell::new(None),
+
+      config: config,
     };
 
-    init_mobs(&server);
     server
   }
 }
@@ -0,0 +1,173 @@
+//! Find clear places on the terrain to put new entities.
+//!
+//! Only terrain that's already loaded is searched, so nothing is generated on the simulation
+//! thread. The terrain around the world spawn is kept loaded for new and respawning players.
+
+use cgmath::{Aabb3, Point, Point2, Point3, Vector3};
+use std::f32;
+
+use common::voxel;
+
+use config;
+use lod;
+use server::Server;
+use terrain;
+use terrain_collision;
+use update_gaia;
+
+/// How far past the searched area the terrain around the world spawn is kept loaded, to make room
+/// for the biggest entities.
+const SPAWN_AREA_MARGIN: f32 = 2.0;
+
+/// Find the low corner of a spot on top of the terrain near `position` (in x/z) where a box of
+/// `size` fits without touching the terrain or any other entity.
+pub fn find(server: &Server, position: &Point2<f32>, size: &Vector3<f32>) -> Option<Point3<f32>> {
+  let physics = server.physics.borrow();
+  search(&server.terrain_loader.terrain, &server.config, position, size, |bounds| {
+    let mut clear = true;
+    physics.misc_tree.intersect_all(bounds, None, &mut |_, _| clear = false);
+    clear
+  })
+}
+
+/// A spot on the ground near the world spawn for a new or respawning entity, if the terrain there
+/// has loaded.
+pub fn world_spawn(server: &Server, size: &Vector3<f32>) -> Option<Point3<f32>> {
+  find(server, &server.config.world_spawn, size)
+}
+
+/// Where to hold an entity until `world_spawn` has somewhere to put it: above the world spawn.
+pub fn above_world_spawn(server: &Server) -> Point3<f32> {
+  let spawn = server.config.world_spawn;
+  Point3::new(spawn.x, server.config.spawn_search_top, spawn.y)
+}
+
+/// Start loading the terrain that `world_spawn` searches, so it's ready by the time anyone joins.
+/// It stays loaded for as long as the server runs.
+pub fn load_world_spawn<RequestBlock>(server: &Server, request_block: &mut RequestBlock)
+  where RequestBlock: FnMut(update_gaia::Message)
+{
+  let config = &server.config;
+  let reach = config.spawn_search_radius + SPAWN_AREA_MARGIN;
+  let spawn = config.world_spawn;
+  for x in (spawn.x - reach).floor() as i32 .. (spawn.x + reach).ceil() as i32 {
+    for y in (config.spawn_search_bottom - 1.0).floor() as i32 .. (config.spawn_search_top + SPAWN_AREA_MARGIN).ceil() as i32 {
+      for z in (spawn.y - reach).floor() as i32 .. (spawn.y + reach).ceil() as i32 {
+        server.terrain_loader.load(
+          &server.id_allocator,
+          &server.physics,
+          &voxel::bounds::new(x, y, z, 0),
+          lod::Full,
+          server.spawn_owner,
+          request_block,
+        );
+      }
+    }
+  }
+}
+
+/// `find`, with `is_clear` checking for other entities.
+fn search<IsClear>(
+  terrain: &terrain::T,
+  config: &config::T,
+  position: &Point2<f32>,
+  size: &Vector3<f32>,
+  is_clear: IsClear,
+) -> Option<Point3<f32>>
+  where IsClear: Fn(&Aabb3<f32>) -> bool
+{
+  // Look at spots an entity-width apart.
+  let steps_x = (config.spawn_search_radius / size.x).floor() as i32;
+  let steps_z = (config.spawn_search_radius / size.z).floor() as i32;
+  let mut offsets = Vec::new();
+  for dx in -steps_x .. steps_x + 1 {
+    for dz in -steps_z .. steps_z + 1 {
+      offsets.push((dx, dz));
+    }
+  }
+  // Try the closest spots first.
+  offsets.sort_by_key(|&(dx, dz)| dx*dx + dz*dz);
+
+  for (dx, dz) in offsets {
+    let x = position.x + dx as f32 * size.x;
+    let z = position.y + dz as f32 * size.z;
+    if let Some(low) = land(terrain, config, x, z, size) {
+      if is_clear(&at(&low, size)) {
+        return Some(low)
+      }
+    }
+  }
+
+  None
+}
+
+fn at(low: &Point3<f32>, size: &Vector3<f32>) -> Aabb3<f32> {
+  Aabb3::new(*low, low.add_v(size))
+}
+
+/// Drop a box down the column at `(x, z)` until it lands on the terrain, and return its low corner.
+/// Unloaded terrain at the top of the column is skipped, but the box has to pass through loaded,
+/// clear terrain right before it lands.
+fn land(terrain: &terrain::T, config: &config::T, x: f32, z: f32, size: &Vector3<f32>) -> Option<Point3<f32>> {
+  let mut y = config.spawn_search_top;
+  let mut clear = false;
+  while y > config.spawn_search_bottom {
+    match terrain_collision::loaded_boxes_in(terrain, &at(&Point3::new(x, y - 1.0, z), size)) {
+      None => {
+        if clear {
+          // There's no telling what's below.
+          return None
+        }
+      },
+      Some(hits) => {
+        if hits.is_empty() {
+          clear = true;
+        } else if clear {
+          // The box was clear at `y`, so everything it hit is below `y`. Land on the highest one.
+          let top = hits.iter().fold(-f32::INFINITY, |top, bounds| top.max(bounds.max.y));
+          return Some(Point3::new(x, top, z))
+        } else {
+          // Buried under terrain whose top isn't loaded.
+          return None
+        }
+      },
+    }
+    y -= 1.0;
+  }
+
+  None
+}
+
+#[test]
+fn spots_are_found_on_loaded_ground() {
+  let terrain = terrain::T::new(terrain::Seed::new(0));
+  let config: config::T = Default::default();
+  let size = Vector3::new(1.0, 2.0, 1.0);
+  // Load a floor at y = 0, with a pillar on it at (0, 0), and open space over everything but
+  // the column at (1, 0).
+  for x in -2 .. 3 {
+    for z in -2 .. 3 {
+      terrain.insert(&voxel::bounds::new(x, -1, z, 0), voxel::Volume(voxel::Material::Stone));
+      if x == 1 && z == 0 {
+        continue
+      }
+      for y in 0 .. 8 {
+        let material = if x == 0 && z == 0 && y < 2 { voxel::Material::Stone } else { voxel::Material::Empty };
+        terrain.insert(&voxel::bounds::new(x, y, z, 0), voxel::Volume(material));
+      }
+    }
+  }
+
+  let low = land(&terrain, &config, 0.0, 0.0, &size).unwrap();
+  assert_eq!((low.x, low.y, low.z), (0.0, 2.0, 0.0));
+  let low = land(&terrain, &config, -1.0, 0.0, &size).unwrap();
+  assert_eq!((low.x, low.y, low.z), (-1.0, 0.0, 0.0));
+  // Nothing's known above the floor here.
+  assert!(land(&terrain, &config, 1.0, 0.0, &size).is_none());
+  // Or anywhere off the loaded area.
+  assert!(land(&terrain, &config, 4.0, 0.0, &size).is_none());
+
+  // Other entities are avoided, and the closest spot is taken.
+  let low = search(&terrain, &config, &Point2::new(0.0, 0.0), &size, |bounds| bounds.min.z.abs() > 0.5).unwrap();
+  assert_eq!((low.x, low.y, low.z), (0.0, 0.0, -1.0));
+}
@@ -4,27 +4,22 @@ use time;
 pub struct Sun {
   // The sun as portions of a 65536-degree circle.
   pub position: u16,
-  pub timer: IntervalTimer,
+  // How far the sun moves each update.
+  pub step: u16,
   pub print_timer: IntervalTimer,
 }
 
 impl Sun {
-  pub fn new(tick_ns: u64) -> Sun {
+  pub fn new(step: u16) -> Sun {
     Sun {
       position: 0,
-      timer: IntervalTimer::new(tick_ns, time::precise_time_ns()),
+      step: step,
       print_timer: IntervalTimer::new(2e9 as u64, time::precise_time_ns()),
     }
   }
 
-  pub fn update(&mut self) -> Option<f32> {
-    let ticks = self.timer.update(time::precise_time_ns());
-
-    if ticks == 0 {
-      return None;
-    }
-
-    self.position += ticks as u16;
+  pub fn update(&mut self) -> f32 {
+    self.position = self.position.wrapping_add(self.step);
 
     // Fraction completed of a full cycle.
     let fraction = (self.position as f32) / 65536.0;
@@ -35,6 +30,6 @@ impl Sun {
       debug!("Sun is at {:.1}%.", fraction * 100.0);
     }
 
-    Some(fraction)
+    fraction
   }
 }
@@ -0,0 +1,172 @@
+//! Collision geometry for the solid parts of voxels.
+
+use cgmath::{Aabb3, Point3};
+#[cfg(test)]
+use cgmath::{EuclideanVector, Vector3};
+
+use common::voxel;
+
+use aabb_tree;
+use terrain;
+
+/// Surface voxels are split into this many columns along x and along z.
+const COLUMNS: u32 = 4;
+/// Columns thinner than this are dropped.
+const MIN_HEIGHT: f32 = 0.001;
+
+/// Approximate the solid part of a voxel with boxes.
+/// Surface voxels are split into columns, each cut off by the voxel's surface plane,
+/// so the boxes follow the rendered surface to within a column's width.
+pub fn boxes(bounds: &voxel::bounds::T, voxel: &voxel::T) -> Vec<Aabb3<f32>> {
+  let (low, high) = bounds.corners();
+  match *voxel {
+    voxel::Volume(voxel::Material::Empty) => Vec::new(),
+    voxel::Volume(_) => vec!(Aabb3::new(low, high)),
+    voxel::Surface(ref surface) => {
+      let vertex = surface.surface_vertex.to_world_vertex(bounds);
+      // Normals point out of the volume.
+      let normal = surface.normal.to_float_normal();
+      let width = (high.x - low.x) / COLUMNS as f32;
+
+      let mut boxes = Vec::new();
+      for i in 0 .. COLUMNS {
+        for k in 0 .. COLUMNS {
+          let x = low.x + width * i as f32;
+          let z = low.z + width * k as f32;
+          let center_x = x + width / 2.0;
+          let center_z = z + width / 2.0;
+          // Along the center of the column, the density is `d - normal.y * y`.
+          let d =
+            normal.x * (vertex.x - center_x) +
+            normal.y * vertex.y +
+            normal.z * (vertex.z - center_z);
+          let (min_y, max_y) =
+            if normal.y.abs() < MIN_HEIGHT {
+              // A vertical wall; the column is either all in or all out.
+              if d > 0.0 {
+                (low.y, high.y)
+              } else {
+                continue
+              }
+            } else if normal.y > 0.0 {
+              (low.y, (d / normal.y).min(high.y))
+            } else {
+              ((d / normal.y).max(low.y), high.y)
+            };
+
+          if max_y - min_y < MIN_HEIGHT {
+            continue
+          }
+
+          boxes.push(
+            Aabb3::new(
+              Point3::new(x, min_y, z),
+              Point3::new(x + width, max_y, z + width),
+            )
+          );
+        }
+      }
+      boxes
+    },
+  }
+}
+
+/// The terrain's collision boxes overlapping `bounds`. Voxels that haven't been loaded are
+/// treated as solid.
+pub fn boxes_in(terrain: &terrain::T, bounds: &Aabb3<f32>) -> Vec<Aabb3<f32>> {
+  collect_boxes(terrain, bounds, |_| Some(voxel::Volume(voxel::Material::Stone))).unwrap()
+}
+
+/// The terrain's collision boxes overlapping `bounds`, or None if any of it hasn't been loaded.
+pub fn loaded_boxes_in(terrain: &terrain::T, bounds: &Aabb3<f32>) -> Option<Vec<Aabb3<f32>>> {
+  collect_boxes(terrain, bounds, |_| None)
+}
+
+/// `unloaded` stands in for the voxels that haven't been loaded; if it returns None, so does this.
+fn collect_boxes<Unloaded>(terrain: &terrain::T, bounds: &Aabb3<f32>, unloaded: Unloaded) -> Option<Vec<Aabb3<f32>>>
+  where Unloaded: Fn(&voxel::bounds::T) -> Option<voxel::T>
+{
+  let mut result = Vec::new();
+  for x in bounds.min.x.floor() as i32 .. bounds.max.x.ceil() as i32 {
+    for y in bounds.min.y.floor() as i32 .. bounds.max.y.ceil() as i32 {
+      for z in bounds.min.z.floor() as i32 .. bounds.max.z.ceil() as i32 {
+        let voxel_bounds = voxel::bounds::new(x, y, z, 0);
+        let voxel =
+          match terrain.get(&voxel_bounds).or_else(|| unloaded(&voxel_bounds)) {
+            None => return None,
+            Some(voxel) => voxel,
+          };
+        result.extend(
+          boxes(&voxel_bounds, &voxel)
+            .into_iter()
+            .filter(|b| aabb_tree::overlap(b, bounds))
+        );
+      }
+    }
+  }
+  Some(result)
+}
+
+/// A plane through (0.5, 0.5, 0.5) sloping up along x by `slope`; solid underneath.
+#[cfg(test)]
+struct Slope {
+  slope: f32,
+}
+
+#[cfg(test)]
+impl voxel::field::T for Slope {
+  fn density(&self, p: &Point3<f32>) -> f32 {
+    0.5 + self.slope * (p.x - 0.5) - p.y
+  }
+
+  fn normal(&self, _: &Point3<f32>) -> Vector3<f32> {
+    Vector3::new(-self.slope, 1.0, 0.0).normalize()
+  }
+}
+
+/// The columns of a unit voxel cut by a `Slope`, in x-major order.
+#[cfg(test)]
+fn slope_columns(slope: f32) -> Vec<Aabb3<f32>> {
+  let bounds = voxel::bounds::new(0, 0, 0, 0);
+  let mosaic = voxel::mosaic::solid::T { field: Slope { slope: slope }, material: voxel::Material::Stone };
+  let voxel = voxel::unwrap(voxel::of_field(&mosaic, &bounds));
+  match voxel {
+    voxel::Surface(_) => {},
+    _ => panic!("Expected a surface voxel."),
+  }
+  boxes(&bounds, &voxel)
+}
+
+#[test]
+fn flat_surfaces_make_even_columns() {
+  let boxes = slope_columns(0.0);
+  assert_eq!(boxes.len(), (COLUMNS * COLUMNS) as usize);
+  for b in &boxes {
+    assert!(b.min.y.abs() < 0.001);
+    assert!((b.max.y - 0.5).abs() < 0.1);
+  }
+}
+
+#[test]
+fn sloped_surfaces_make_stepped_columns() {
+  let boxes = slope_columns(0.5);
+  assert_eq!(boxes.len(), (COLUMNS * COLUMNS) as usize);
+  for b in &boxes {
+    let center_x = (b.min.x + b.max.x) / 2.0;
+    assert!(b.min.y.abs() < 0.001);
+    assert!((b.max.y - (0.5 + 0.5 * (center_x - 0.5))).abs() < 0.1);
+  }
+  // Columns get taller along x.
+  assert!(boxes[0].max.y < boxes[boxes.len() - 1].max.y);
+}
+
+#[test]
+fn volumes_are_all_or_nothing() {
+  let bounds = voxel::bounds::new(1, 2, 3, 0);
+  let solid = boxes(&bounds, &voxel::Volume(voxel::Material::Dirt));
+  assert_eq!(solid.len(), 1);
+  let close = |a: Point3<f32>, b: Point3<f32>| (a.x - b.x).abs() + (a.y - b.y).abs() + (a.z - b.z).abs() < 0.001;
+  assert!(close(solid[0].min, Point3::new(1.0, 2.0, 3.0)));
+  assert!(close(solid[0].max, Point3::new(2.0, 3.0, 4.0)));
+  assert!(boxes(&bounds, &voxel::Volume(voxel::Material::Empty)).is_empty());
+}
@@ -1,6 +1,7 @@
 use cgmath::{Aabb3};
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::cell::RefCell;
+use std::sync::Arc;
 use stopwatch;
 
 use common::entity_id;
@@ -20,19 +21,19 @@ use update_gaia::LoadReason;
 /// Each terrain::TerrainBlock can be owned by a set of owners, each of which can independently request LODs.
 /// The maximum lod::T requested is the one that is actually loaded.
 pub struct T {
-  pub terrain: terrain::T,
-  pub in_progress_terrain: Mutex<in_progress_terrain::T>,
-  pub lod_map: Mutex<lod::Map>,
-  pub loaded: Mutex<HashMap<voxel::bounds::T, Vec<entity_id::T>>>,
+  pub terrain: Arc<terrain::T>,
+  pub in_progress_terrain: RefCell<in_progress_terrain::T>,
+  pub lod_map: RefCell<lod::Map>,
+  pub loaded: RefCell<HashMap<voxel::bounds::T, Vec<entity_id::T>>>,
 }
 
 impl T {
-  pub fn new() -> T {
+  pub fn new(terrain: Arc<terrain::T>) -> T {
     T {
-      terrain: terrain::T::new(terrain::Seed::new(0)),
-      in_progress_terrain: Mutex::new(in_progress_terrain::T::new()),
-      lod_map: Mutex::new(lod::Map::new()),
-      loaded: Mutex::new(HashMap::new()),
+      terrain: terrain,
+      in_progress_terrain: RefCell::new(in_progress_terrain::T::new()),
+      lod_map: RefCell::new(lod::Map::new()),
+      loaded: RefCell::new(HashMap::new()),
     }
   }
 
@@ -40,8 +41,8 @@ impl T {
 
   pub fn load<LoadBlock>(
     &self,
-    id_allocator: &Mutex<id_allocator::T<entity_id::T>>,
-    physics: &Mutex<Physics>,
+    id_allocator: &RefCell<id_allocator::T<entity_id::T>>,
+    physics: &RefCell<Physics>,
     block_position: &voxel::bounds::T,
     new_lod: lod::T,
     owner: lod::OwnerId,
@@ -50,8 +51,8 @@ impl T {
   {
     let prev_lod;
     let max_lod_changed: bool;
-    let mut lod_map = self.lod_map.lock().unwrap();
-    let mut in_progress_terrain = self.in_progress_terrain.lock().unwrap();
+    let mut lod_map = self.lod_map.borrow_mut();
+    let mut in_progress_terrain = self.in_progress_terrain.borrow_mut();
     match lod_map.get(block_position, owner) {
       Some((Some(prev), lods)) => {
         prev_lod = Some(prev);
@@ -107,7 +108,7 @@ impl T {
     block: &LoadedTerrain,
     position: &voxel::bounds::T,
     owner: lod::OwnerId,
-    physics: &Mutex<Physics>,
+    physics: &RefCell<Physics>,
     lod_map: &mut lod::Map,
     in_progress_terrain: &mut in_progress_terrain::T,
     loaded: &mut HashMap<voxel::bounds::T, Vec<entity_id::T>>,
@@ -129,10 +130,10 @@ impl T {
         }
         lod::Full => {
           stopwatch::time("terrain_loader.load.unload", || {
-            let ids = loaded.get(position).unwrap();
-            let mut physics = physics.lock().unwrap();
+            let ids = loaded.remove(position).unwrap();
+            let mut physics = physics.borrow_mut();
             for id in ids {
-              physics.remove_terrain(*id);
+              physics.remove_terrain(id);
             }
           });
         },
@@ -140,21 +141,23 @@ impl T {
     );
 
     stopwatch::time("terrain_loader.load.physics", || {
-      let mut physics = physics.lock().unwrap();
+      let mut physics = physics.borrow_mut();
       for &(ref id, ref bounds) in &block.bounds {
         physics.insert_terrain(*id, bounds);
       }
     });
+
+    loaded.insert(*position, block.bounds.iter().map(|&(id, _)| id).collect());
   }
 
   pub fn unload(
     &self,
-    physics: &Mutex<Physics>,
+    physics: &RefCell<Physics>,
     block_position: &voxel::bounds::T,
     owner: lod::OwnerId,
   ) {
     let (_, mlod_change) =
-      self.lod_map.lock().unwrap().remove(*block_position, owner);
+      self.lod_map.borrow_mut().remove(*block_position, owner);
 
     let lod_change;
     match mlod_change {
@@ -167,18 +170,18 @@ impl T {
     lod_change.loaded.map(|loaded_lod| {
       match loaded_lod {
         lod::Placeholder => {
-          self.in_progress_terrain.lock().unwrap().remove(physics, block_position);
+          self.in_progress_terrain.borrow_mut().remove(physics, block_position);
         }
         lod::Full => {
           stopwatch::time("terrain_loader.unload", || {
-            match self.loaded.lock().unwrap().get(block_position) {
+            match self.loaded.borrow_mut().remove(block_position) {
               None => {
                 // Unloaded before the load request completed.
               },
               Some(ids) => {
-                let mut physics = physics.lock().unwrap();
+                let mut physics = physics.borrow_mut();
                 for id in ids {
-                  physics.remove_terrain(*id);
+                  physics.remove_terrain(id);
                 }
               },
             }
@@ -0,0 +1,53 @@
+//! The server's fixed-timestep clock.
+
+use std::ops::Range;
+
+use common::interval_timer::IntervalTimer;
+use common::protocol;
+
+/// The most updates run at once to catch up after falling behind. Any more are dropped,
+/// and the world runs slower than real time until the server can keep up again.
+const MAX_CATCH_UP: u64 = 5;
+
+/// Counts off fixed-length updates, and numbers them.
+pub struct T {
+  timer: IntervalTimer,
+  next: protocol::Tick,
+}
+
+/// A clock that starts at `now` and has an update due every `interval` nanoseconds.
+pub fn new(interval: u64, now: u64) -> T {
+  T {
+    timer: IntervalTimer::new(interval, now),
+    next: 0,
+  }
+}
+
+impl T {
+  /// The time the next tick is due.
+  pub fn next_deadline(&self) -> u64 {
+    self.timer.next()
+  }
+
+  /// The ticks due to be run at `now`, oldest first.
+  pub fn due(&mut self, now: u64) -> Range<protocol::Tick> {
+    let mut elapsed = self.timer.update(now);
+    if elapsed > MAX_CATCH_UP {
+      warn!("Running {} updates behind; skipping {} of them.", elapsed, elapsed - MAX_CATCH_UP);
+      elapsed = MAX_CATCH_UP;
+    }
+    let first = self.next;
+    self.next += elapsed;
+    first .. self.next
+  }
+}
+
+#[test]
+fn catches_up_to_a_limit() {
+  let mut tick = new(10, 10);
+  assert_eq!(tick.due(5), 0 .. 0);
+  assert_eq!(tick.due(25), 0 .. 2);
+  assert_eq!(tick.due(29), 2 .. 2);
+  assert_eq!(tick.due(30), 2 .. 3);
+  assert_eq!(tick.due(1000), 3 .. 3 + MAX_CATCH_UP);
+}
@@ -1,15 +1,20 @@
 /// Creator of the earth.
 
-use cgmath::{Aabb3};
+use cgmath::{Aabb3, Point3};
+use std::cmp::{min, max};
+use std::collections::HashMap;
 use stopwatch;
 
 use common;
-use common::id_allocator;
+use common::entity_id;
 use common::protocol;
 use common::voxel;
 
+use history;
 use lod;
 use server::Server;
+use terrain;
+use terrain_collision;
 use terrain_loader;
 use voxel_data;
 
@@ -20,105 +25,242 @@ pub enum LoadReason {
 }
 
 pub enum Message {
+  /// Ask for some voxels to be loaded. The generation is handed off to the terrain workers.
   Load(Vec<voxel::bounds::T>, LoadReason),
-  Brush(voxel_data::brush::T<Box<voxel_data::mosaic::T<common::voxel::Material> + Send>>),
+  /// Voxels that have been generated by a terrain worker, and are ready to be inserted.
+  Generated(Vec<(voxel::bounds::T, voxel::T)>, LoadReason),
+  /// Apply a brush to the terrain, recording what it overwrote in a player's history, if it's theirs.
+  Brush(voxel_data::brush::T<Box<voxel_data::mosaic::T<common::voxel::Material> + Send>>, Option<entity_id::T>),
+  /// Undo a player's last edit.
+  Undo(entity_id::T),
+  /// Redo a player's last undone edit.
+  Redo(entity_id::T),
 }
 
-// TODO: Consider adding terrain loads to a thread pool instead of having one monolithic separate thread.
-pub fn update_gaia(
+/// Apply a gaia update. This is where everything gets inserted into the world, so it should only
+/// be called from one thread; the expensive voxel generation is sent to `generate` instead.
+pub fn update_gaia<Generate>(
   server: &Server,
   update: Message,
-) {
+  generate: &mut Generate,
+) where
+  Generate: FnMut(Vec<voxel::bounds::T>, LoadReason),
+{
   stopwatch::time("update_gaia", move || {
     match update {
       Message::Load(voxel_bounds, load_reason) => {
+        generate(voxel_bounds, load_reason);
+      },
+      Message::Generated(voxels, load_reason) => {
         stopwatch::time("terrain.load", || {
-          load(server, voxel_bounds, load_reason);
+          load(server, voxels, load_reason);
         });
       },
-      Message::Brush(brush) => {
+      Message::Brush(brush, player_id) => {
         let mut updates = Vec::new();
+        let mut overwritten = HashMap::new();
         server.terrain_loader.terrain.brush(
           &brush,
-          |block, bounds| {
+          |block, previous, bounds| {
             trace!("update bounds {:?}", bounds);
             updates.push((*bounds, *block));
+            // Coarser voxels are rebuilt from the finest ones, so only those need to be put back.
+            if bounds.lg_size == 0 {
+              if let Some(previous) = previous {
+                overwritten.entry(*bounds).or_insert(*previous);
+              }
+            }
           },
         );
 
-        let mut clients = server.clients.lock().unwrap();
-        for (_, client) in clients.iter_mut() {
-          client.send(
-            protocol::ServerToClient::Voxels(
-              updates.clone(),
-              protocol::VoxelReason::Updated,
-            )
-          );
+        if let Some(player_id) = player_id {
+          if !overwritten.is_empty() {
+            if let Some(player) = server.players.borrow_mut().get_mut(&player_id) {
+              player.history.record(overwritten.into_iter().collect());
+            }
+          }
+        }
+
+        apply_updates(server, updates, &brush.bounds);
+      },
+      Message::Undo(player_id) => {
+        let mut players = server.players.borrow_mut();
+        let undone =
+          players.get_mut(&player_id)
+          .map_or(false, |player| player.history.undo(|edit| restore(server, edit)));
+        if !undone {
+          debug!("Nothing for {:?} to undo.", player_id);
+        }
+      },
+      Message::Redo(player_id) => {
+        let mut players = server.players.borrow_mut();
+        let redone =
+          players.get_mut(&player_id)
+          .map_or(false, |player| player.history.redo(|edit| restore(server, edit)));
+        if !redone {
+          debug!("Nothing for {:?} to redo.", player_id);
         }
       },
     };
   })
 }
 
+/// Write earlier voxels back into the terrain, and return the ones they replaced.
+/// This doesn't borrow `server.players`, so it can be run from inside a player's history.
+fn restore(server: &Server, edit: history::Edit) -> history::Edit {
+  if edit.is_empty() {
+    return edit
+  }
+
+  // Edits only hold the finest voxels; the coarser ones rebuilt from them are all within these.
+  let (first, _) = edit[0];
+  let changed =
+    edit.iter().fold(
+      Aabb3::new(Point3::new(first.x, first.y, first.z), Point3::new(first.x + 1, first.y + 1, first.z + 1)),
+      |changed, &(bounds, _)| {
+        Aabb3::new(
+          Point3::new(min(changed.min.x, bounds.x), min(changed.min.y, bounds.y), min(changed.min.z, bounds.z)),
+          Point3::new(max(changed.max.x, bounds.x + 1), max(changed.max.y, bounds.y + 1), max(changed.max.z, bounds.z + 1)),
+        )
+      },
+    );
+
+  let mut replaced = Vec::new();
+  let mut updates = Vec::new();
+  server.terrain_loader.terrain.restore(
+    &edit,
+    |block, previous, bounds| {
+      updates.push((*bounds, *block));
+      // Everything in an edit was written by a brush before, so it's still stored.
+      if let Some(previous) = previous {
+        replaced.push((*bounds, *previous));
+      }
+    },
+  );
+  apply_updates(server, updates, &changed);
+
+  replaced
+}
+
+/// Save terrain changes with the world, and pass them on to physics, mobs and clients.
+/// `changed` is the box of voxels (with an exclusive high corner) that all the updates are within.
+fn apply_updates(server: &Server, updates: Vec<(voxel::bounds::T, voxel::T)>, changed: &Aabb3<i32>) {
+  server.edits.borrow_mut().extend(updates.iter().cloned());
+
+  stopwatch::time("update_gaia.brush.physics", || {
+    update_physics(server, &updates);
+  });
+
+  for (_, mob) in server.mobs.borrow_mut().iter_mut() {
+    mob.navigator.terrain_changed(changed);
+  }
+
+  let mut clients = server.clients.borrow_mut();
+  for (_, client) in clients.iter_mut() {
+    client.send(
+      protocol::ServerToClient::Voxels(
+        updates.clone(),
+        protocol::VoxelReason::Updated,
+      )
+    );
+  }
+}
+
+/// Generate the voxels for a load request, and package them up to be inserted by `update_gaia`.
+/// This only briefly locks the voxel tree to look for existing voxels, so it's safe to run
+/// on several worker threads at once.
+pub fn generate(
+  terrain: &terrain::T,
+  voxel_bounds: Vec<voxel::bounds::T>,
+  load_reason: LoadReason,
+) -> Message {
+  stopwatch::time("terrain.generate", || {
+    let voxels =
+      voxel_bounds.into_iter()
+      .map(|bounds| {
+        let voxel = terrain.get(&bounds).unwrap_or_else(|| terrain.generate(&bounds));
+        (bounds, voxel)
+      })
+      .collect();
+    Message::Generated(voxels, load_reason)
+  })
+}
+
+/// Allocate physics objects for the solid parts of a voxel.
+fn collision_boxes(
+  server: &Server,
+  bounds: &voxel::bounds::T,
+  voxel: &voxel::T,
+) -> Vec<(entity_id::T, Aabb3<f32>)> {
+  terrain_collision::boxes(bounds, voxel)
+    .into_iter()
+    .map(|aabb| (server.id_allocator.borrow_mut().allocate(), aabb))
+    .collect()
+}
+
+/// Rebuild the physics of any fully-loaded blocks that were brushed.
+fn update_physics(server: &Server, updates: &[(voxel::bounds::T, voxel::T)]) {
+  let mut loaded = server.terrain_loader.loaded.borrow_mut();
+  for &(ref bounds, ref voxel) in updates {
+    let ids =
+      match loaded.get_mut(bounds) {
+        None => continue,
+        Some(ids) => ids,
+      };
+
+    let boxes = collision_boxes(server, bounds, voxel);
+    let mut physics = server.physics.borrow_mut();
+    for id in ids.drain(..) {
+      physics.remove_terrain(id);
+    }
+    for (id, aabb) in boxes {
+      physics.insert_terrain(id, &aabb);
+      ids.push(id);
+    }
+  }
+}
+
 #[inline(never)]
 fn load(
   server: &Server,
-  voxel_bounds: Vec<voxel::bounds::T>,
+  voxels: Vec<(voxel::bounds::T, voxel::T)>,
   load_reason: LoadReason,
 ) {
-  // TODO: Just lock `terrain` for the check and then the move;
-  // don't lock for the whole time where we're generating the block.
-  let mut lod_map = server.terrain_loader.lod_map.lock().unwrap();
-  let mut in_progress_terrain = server.terrain_loader.in_progress_terrain.lock().unwrap();
   match load_reason {
     LoadReason::Local(owner) => {
-      for voxel_bounds in voxel_bounds.into_iter() {
-        server.terrain_loader.terrain.load(
+      let mut lod_map = server.terrain_loader.lod_map.borrow_mut();
+      let mut in_progress_terrain = server.terrain_loader.in_progress_terrain.borrow_mut();
+      for (voxel_bounds, voxel) in voxels.into_iter() {
+        let voxel = server.terrain_loader.terrain.insert(&voxel_bounds, voxel);
+        let bounds = collision_boxes(server, &voxel_bounds, &voxel);
+        // TODO: Check that this block isn't stale, i.e. should still be loaded.
+        // Maybe this should just ping the original thread, same as we ping the client.
+        terrain_loader::T::insert_block(
+          &terrain_loader::LoadedTerrain { bounds: bounds },
           &voxel_bounds,
-          |block| {
-            let bounds =
-              match block {
-                &voxel::Volume(voxel::Material::Empty) => Vec::new(),
-                _ => {
-                  let (low, high) = voxel_bounds.corners();
-                  let id = id_allocator::allocate(&server.id_allocator);
-                  vec!((id, Aabb3::new(low, high)))
-                },
-              };
-            // TODO: Check that this block isn't stale, i.e. should still be loaded.
-            // Maybe this should just ping the original thread, same as we ping the client.
-            terrain_loader::T::insert_block(
-              &terrain_loader::LoadedTerrain { bounds: bounds },
-              &voxel_bounds,
-              owner,
-              &server.physics,
-              &mut *lod_map,
-              &mut *in_progress_terrain,
-              &mut *server.terrain_loader.loaded.lock().unwrap(),
-            );
-          }
+          owner,
+          &server.physics,
+          &mut *lod_map,
+          &mut *in_progress_terrain,
+          &mut *server.terrain_loader.loaded.borrow_mut(),
         );
       }
     },
     LoadReason::ForClient(id) => {
-      let mut voxels = Vec::new();
-      for voxel_bounds in voxel_bounds.into_iter() {
-        server.terrain_loader.terrain.load(
-          &voxel_bounds,
-          |voxel| {
-            voxels.push((voxel_bounds, *voxel));
-          },
+      let voxels: Vec<_> =
+        voxels.into_iter()
+        .map(|(bounds, voxel)| (bounds, server.terrain_loader.terrain.insert(&bounds, voxel)))
+        .collect();
+
+      // The client may have been kicked since it asked.
+      if let Some(client) = server.clients.borrow_mut().get_mut(&id) {
+        client.send(
+          protocol::ServerToClient::Voxels(
+            voxels,
+            protocol::VoxelReason::Requested,
+          )
         );
       }
-
-      let mut clients = server.clients.lock().unwrap();
-      let client = clients.get_mut(&id).unwrap();
-      client.send(
-        protocol::ServerToClient::Voxels(
-          voxels,
-          protocol::VoxelReason::Requested,
-        )
-      );
     },
   }
 }
@@ -1,109 +1,216 @@
-use cgmath::{Point, Point3, Vector, Vector3};
-use std::ops::Neg;
+use cgmath::{Aabb3, Point, Point3, Vector, Vector3};
+use std::collections::HashSet;
 use stopwatch;
 
+use common::entity_id;
 use common::protocol;
 use common::surroundings_loader::LoadType;
 use common::voxel;
 
 use lod;
 use mob;
+use mob_spawner;
+use player::Player;
+use server;
 use server::Server;
+use spawn;
+use terrain;
 use update_gaia;
 
-// TODO: Consider removing the IntervalTimer.
-
+/// Run world update number `tick`.
+/// Given the same client messages and terrain loads between updates, the same updates produce
+/// the same world.
 pub fn update_world<RequestBlock>(
   server: &Server,
+  tick: protocol::Tick,
   request_block: &mut RequestBlock,
 ) where
   RequestBlock: FnMut(update_gaia::Message),
 {
   stopwatch::time("update_world", || {
     stopwatch::time("update_world.player", || {
-      for (_, player) in server.players.lock().unwrap().iter_mut() {
-        player.update(server, request_block);
+      for (_, player) in server.players.borrow_mut().iter_mut() {
+        update_player(server, player, request_block);
       }
 
-      let players: Vec<_> = server.players.lock().unwrap().keys().cloned().collect();
-      for (_, client) in server.clients.lock().unwrap().iter_mut() {
+      let players: Vec<_> = server.players.borrow_mut().keys().cloned().collect();
+      for (_, client) in server.clients.borrow_mut().iter_mut() {
         for &id in &players {
-          let bounds = *server.physics.lock().unwrap().get_bounds(id).unwrap();
-          client.send(protocol::ServerToClient::UpdatePlayer(id, bounds));
+          let bounds = *server.physics.borrow_mut().get_bounds(id).unwrap();
+          client.send(protocol::ServerToClient::UpdatePlayer(tick, id, bounds));
         }
       }
     });
 
     stopwatch::time("update_world.mobs", || {
-      for (_, mob) in server.mobs.lock().unwrap().iter_mut() {
-        let position =
-          Point3::new(
-            mob.position.x as i32,
-            mob.position.y as i32,
-            mob.position.z as i32,
-          );
-
-        let owner_id = mob.owner_id;
-        for (position, load_type) in mob.surroundings_loader.updates(&position) {
-          load_placeholders(
-            owner_id,
-            server,
-            request_block,
-            &voxel::bounds::new(position.x, position.y, position.z, 0),
-            load_type,
-          )
-        }
+      let players: Vec<Point3<f32>> =
+        server.players.borrow_mut().values()
+        .filter(|player| player.respawn_ticks.is_none())
+        .map(|player| player.position)
+        .collect();
 
-        {
-          let behavior = mob.behavior;
-          (behavior)(server, mob);
+      let mut mobs = server.mobs.borrow_mut();
+      mob::update_surroundings(server, &mut mobs, &players, request_block);
+      for (_, mob) in mobs.iter_mut() {
+        // Rather than simulate against placeholders, wait for the real terrain.
+        if !mob.is_active(&players) || !mob.is_terrain_loaded(server) {
+          continue
         }
 
-        mob.speed = mob.speed.add_v(&-Vector3::new(0.0, 0.1, 0.0 as f32));
+        mob.think(server);
+
+        mob.speed = mob.speed.add_v(&-Vector3::new(0.0, mob::GRAVITY, 0.0));
 
-        // TODO: This logic is dumb (isolating along components shouldn't be a thing). Change it.
         let delta_p = mob.speed;
-        if delta_p.x != 0.0 {
-          translate_mob(server, mob, &Vector3::new(delta_p.x, 0.0, 0.0));
-        }
-        if delta_p.y != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0));
-        }
-        if delta_p.z != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, 0.0, delta_p.z));
+        translate_mob(server, tick, mob, &delta_p);
+
+        if mob.position.y < server.config.kill_plane {
+          respawn_mob(server, mob);
         }
       }
     });
 
-    server.sun.lock().unwrap().update().map(|fraction| {
-      for (_, client) in server.clients.lock().unwrap().iter_mut() {
-        client.send(protocol::ServerToClient::UpdateSun(fraction));
-      }
-    });
+    if tick % (server::MOB_SPAWN_INTERVAL_SECONDS * server.config.updates_per_second) == 0 {
+      stopwatch::time("update_world.mob_spawner", || {
+        mob_spawner::update(server);
+      });
+    }
+
+    let fraction = server.sun.borrow_mut().update();
+    send_to_all(server, protocol::ServerToClient::UpdateSun(tick, fraction));
+
+    if tick % (server::STATS_INTERVAL_SECONDS * server.config.updates_per_second) == 0 {
+      stopwatch::time("update_world.evict_voxels", || {
+        evict_voxels(server);
+      });
+    }
   });
 }
 
+fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
+  bounds.min.add_v(&bounds.max.to_vec()).mul_s(0.5)
+}
+
+fn send_to_all(server: &Server, msg: protocol::ServerToClient) {
+  for (_, client) in server.clients.borrow_mut().iter_mut() {
+    client.send(msg.clone());
+  }
+}
+
+fn size_of(server: &Server, id: entity_id::T) -> Vector3<f32> {
+  let physics = server.physics.borrow_mut();
+  let bounds = physics.get_bounds(id).unwrap();
+  bounds.max.sub_p(&bounds.min)
+}
+
+/// Move an entity so its low corner is at `low`. Returns the entity's new bounds.
+fn teleport(server: &Server, id: entity_id::T, low: &Point3<f32>) -> Aabb3<f32> {
+  let bounds = Aabb3::new(*low, low.add_v(&size_of(server, id)));
+  server.physics.borrow_mut().teleport_misc(id, &bounds);
+  bounds
+}
+
+fn update_player<RequestBlock>(
+  server: &Server,
+  player: &mut Player,
+  request_block: &mut RequestBlock,
+) where
+  RequestBlock: FnMut(update_gaia::Message),
+{
+  match player.respawn_ticks {
+    None => {},
+    Some(0) => {
+      // Falling in from above could be fatal, so stay put until there's somewhere to land.
+      let low =
+        match spawn::world_spawn(server, &size_of(server, player.entity_id)) {
+          None => return,
+          Some(low) => low,
+        };
+      let bounds = teleport(server, player.entity_id, &low);
+      player.respawn(center(&bounds));
+      info!("Player {:?} respawned at {:?}.", player.entity_id, player.position);
+      send_to_all(server, protocol::ServerToClient::PlayerRespawned(player.entity_id, player.position));
+      send_to_all(server, protocol::ServerToClient::UpdateHealth(player.entity_id, player.health));
+      return
+    },
+    Some(ticks) => {
+      player.respawn_ticks = Some(ticks - 1);
+      return
+    },
+  }
+
+  let health = player.health;
+  player.update(server, request_block);
+
+  if player.position.y < server.config.kill_plane {
+    player.health = 0;
+  }
+
+  if player.health != health {
+    send_to_all(server, protocol::ServerToClient::UpdateHealth(player.entity_id, player.health));
+  }
+
+  if player.health == 0 {
+    info!("Player {:?} died.", player.entity_id);
+    player.die();
+    send_to_all(server, protocol::ServerToClient::PlayerDied(player.entity_id));
+  }
+}
+
+fn respawn_mob(server: &Server, mob: &mut mob::Mob) {
+  // Mobs don't take fall damage, so they can be dropped in if there's nowhere to land yet.
+  let low =
+    spawn::world_spawn(server, &size_of(server, mob.entity_id))
+    .unwrap_or_else(|| spawn::above_world_spawn(server));
+  let bounds = teleport(server, mob.entity_id, &low);
+  mob.position = center(&bounds);
+  mob.speed = Vector3::new(0.0, 0.0, 0.0);
+}
+
+fn evict_voxels(server: &Server) {
+  let voxels = &server.terrain_loader.terrain.voxels;
+  // Anything an owner still holds (e.g. a player's surroundings, or the world spawn) stays.
+  let held: HashSet<Point3<i32>> =
+    server.terrain_loader.lod_map.borrow().positions().iter()
+    .map(terrain::voxels::region_of)
+    .collect();
+  let evicted = voxels.evict(server.config.voxel_memory_budget_mib << 20, |region| held.contains(region));
+  if evicted > 0 {
+    debug!("Evicted {} voxel regions.", evicted);
+  }
+
+  let stats = voxels.stats();
+  info!(
+    "Voxel tree: {} regions ({} edited), ~{}KiB.",
+    stats.regions,
+    stats.edited_regions,
+    stats.bytes / 1024,
+  );
+}
+
 fn translate_mob(
   server: &Server,
+  tick: protocol::Tick,
   mob: &mut mob::Mob,
   delta_p: &Vector3<f32>,
 ) {
-  let bounds;
-  {
-    let mut physics = server.physics.lock().unwrap();
-    if physics.translate_misc(mob.entity_id, *delta_p).is_some() {
-      mob.speed.add_self_v(&delta_p.neg());
-      return;
-    } else {
-      bounds = *physics.get_bounds(mob.entity_id).unwrap();
+  let (movement, bounds) = {
+    let mut physics = server.physics.borrow_mut();
+    let movement = physics.move_misc(mob.entity_id, *delta_p, server.config.max_step_height);
+    (movement, *physics.get_bounds(mob.entity_id).unwrap())
+  };
+
+  mob.position.add_self_v(&movement.delta);
+  for normal in &movement.normals {
+    let into = mob.speed.dot(normal);
+    if into < 0.0 {
+      mob.speed.sub_self_v(&normal.mul_s(into));
     }
   }
 
-  mob.position.add_self_v(delta_p);
-
-  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+  for (_, client) in server.clients.borrow_mut().iter_mut() {
     client.send(
-      protocol::ServerToClient::UpdateMob(mob.entity_id, bounds),
+      protocol::ServerToClient::UpdateMob(tick, mob.entity_id, bounds),
     );
   }
 }
@@ -0,0 +1,56 @@
+//! Save and load the edited parts of the world. Everything else is regenerated from the terrain.
+
+use bincode;
+use bincode::SizeLimit;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+
+use common::voxel;
+
+use terrain;
+
+/// Every voxel that's been changed by a brush, as of the last change.
+pub type Edits = HashMap<voxel::bounds::T, voxel::T>;
+
+/// Write `edits` to the file at `path`. The old file is only replaced once the new one is complete.
+pub fn save(edits: &Edits, path: &str) -> Result<(), String> {
+  let edits: Vec<(voxel::bounds::T, voxel::T)> = edits.iter().map(|(&b, &v)| (b, v)).collect();
+  let bytes =
+    try!(
+      bincode::rustc_serialize::encode(&edits, SizeLimit::Infinite)
+      .map_err(|err| format!("Couldn't encode the world: {:?}", err))
+    );
+
+  let tmp_path = format!("{}.tmp", path);
+  let write = || -> io::Result<()> {
+    let mut file = try!(File::create(&tmp_path));
+    try!(file.write_all(&bytes));
+    try!(file.sync_all());
+    fs::rename(&tmp_path, path)
+  };
+  write().map_err(|err| format!("Couldn't write {}: {}", path, err))
+}
+
+/// Read the edits saved at `path` into `terrain`, and return them.
+/// If there's no file at `path`, there are no edits.
+pub fn load(terrain: &terrain::T, path: &str) -> Result<Edits, String> {
+  let mut bytes = Vec::new();
+  match File::open(path) {
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+    Err(err) => return Err(format!("Couldn't open {}: {}", path, err)),
+    Ok(mut file) => {
+      try!(file.read_to_end(&mut bytes).map_err(|err| format!("Couldn't read {}: {}", path, err)));
+    },
+  }
+
+  let edits: Vec<(voxel::bounds::T, voxel::T)> =
+    try!(
+      bincode::rustc_serialize::decode(&bytes)
+      .map_err(|err| format!("{} isn't a valid world file: {:?}", path, err))
+    );
+  for &(ref bounds, voxel) in &edits {
+    terrain.voxels.set(bounds, voxel);
+  }
+  Ok(edits.into_iter().collect())
+}
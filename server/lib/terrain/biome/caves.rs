@@ -5,6 +5,8 @@ use noise::{Seed, perlin3};
 
 use common::voxel;
 
+use super::layers;
+
 #[allow(missing_docs)]
 pub struct T {
   pub seed: Seed,
@@ -59,7 +61,7 @@ impl voxel::mosaic::T<voxel::Material> for T {
   fn material(&self, p: &Point3<f32>) -> Option<voxel::Material> {
     Some(
       if voxel::field::T::density(self, p) >= 0.0 {
-        voxel::Material::Stone
+        layers::rock(&self.seed, p)
       } else {
         voxel::Material::Empty
       }
@@ -5,10 +5,16 @@ use noise::{Seed, Brownian2, Brownian3, perlin2, perlin3};
 
 use common::voxel;
 
+use super::layers;
+
+/// The altitude of the (dry) sea floor; sand is placed on the surface up to a little above it.
+const SEA_LEVEL: f32 = -8.0;
+
 #[allow(missing_docs)]
 pub struct T {
   height: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
   features: Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>,
+  layers: layers::T,
   seed: Seed,
 }
 
@@ -30,6 +36,14 @@ pub fn new(seed: Seed) -> T {
       .persistence(8.0)
       .lacunarity(1.0 / 4.0)
     ,
+    layers:
+      layers::T {
+        topsoil_depth: 1.0,
+        dirt_depth: 4.0,
+        max_topsoil_slope: 0.6,
+        sand_line: Some(SEA_LEVEL + 2.0),
+        snow_line: Some(24.0),
+      },
   }
 }
 
@@ -73,9 +87,11 @@ impl voxel::field::T for T {
 
 impl voxel::mosaic::T<voxel::Material> for T {
   fn material(&self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let depth = voxel::field::T::density(self, p);
     Some(
-      if voxel::field::T::density(self, p) >= 0.0 {
-        voxel::Material::Terrain
+      if depth >= 0.0 {
+        let normal = voxel::field::T::normal(self, p);
+        self.layers.material(&self.seed, p, depth, &normal)
       } else {
         voxel::Material::Empty
       }
@@ -0,0 +1,88 @@
+//! Pick materials for the solid parts of a biome based on depth, altitude and slope.
+
+use cgmath::{Point3, Vector3};
+use noise::{Seed, perlin3};
+
+use common::voxel;
+
+/// Frequency of the noise used to place ore veins. This is shared by every biome,
+/// so veins line up where biomes meet.
+const ORE_FREQUENCY: f64 = 1.0 / 16.0;
+/// The thickness of ore veins, in units of noise.
+const ORE_THICKNESS: f64 = 0.03;
+
+/// The material of deep rock at `p`: stone, with veins of ore.
+pub fn rock(seed: &Seed, p: &Point3<f32>) -> voxel::Material {
+  let ore =
+    perlin3(
+      seed,
+      &[
+        (p.x as f64) * ORE_FREQUENCY,
+        (p.y as f64) * ORE_FREQUENCY,
+        (p.z as f64) * ORE_FREQUENCY,
+      ],
+    );
+  if ore.abs() < ORE_THICKNESS {
+    voxel::Material::Ore
+  } else {
+    voxel::Material::Stone
+  }
+}
+
+/// The parameters used to layer the materials within a biome.
+#[allow(missing_docs)]
+pub struct T {
+  /// Depth of the grass/topsoil layer.
+  pub topsoil_depth: f32,
+  /// Depth at which dirt turns into stone.
+  pub dirt_depth: f32,
+  /// Slopes steeper than this (as the y component of the surface normal) lose their topsoil.
+  pub max_topsoil_slope: f32,
+  /// Sand is placed on the surface below this altitude.
+  pub sand_line: Option<f32>,
+  /// Snow is placed on the surface above this altitude.
+  pub snow_line: Option<f32>,
+}
+
+impl T {
+  /// The material at `p`, given how deep `p` is below the surface and the surface normal there.
+  /// `depth` should be nonnegative; points above the surface aren't solid.
+  pub fn material(
+    &self,
+    seed: &Seed,
+    p: &Point3<f32>,
+    depth: f32,
+    normal: &Vector3<f32>,
+  ) -> voxel::Material {
+    if depth >= self.dirt_depth {
+      return rock(seed, p)
+    }
+
+    // Normals point out of the volume, so flat ground has a normal of roughly +y.
+    let is_flat = normal.y >= self.max_topsoil_slope;
+
+    if depth < self.topsoil_depth && is_flat {
+      if let Some(snow_line) = self.snow_line {
+        if p.y >= snow_line {
+          return voxel::Material::Snow
+        }
+      }
+    }
+
+    if let Some(sand_line) = self.sand_line {
+      if p.y < sand_line {
+        return voxel::Material::Sand
+      }
+    }
+
+    if !is_flat {
+      return voxel::Material::Stone
+    }
+
+    if depth < self.topsoil_depth {
+      voxel::Material::Terrain
+    } else {
+      voxel::Material::Dirt
+    }
+  }
+}
@@ -2,4 +2,5 @@
 
 pub mod caves;
 pub mod hills;
+pub mod layers;
 pub mod mountains;
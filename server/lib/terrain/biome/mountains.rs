@@ -5,10 +5,13 @@ use noise::{Seed, Brownian2, Brownian3, perlin2, perlin3};
 
 use common::voxel;
 
+use super::layers;
+
 #[allow(missing_docs)]
 pub struct T {
   pub height: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
   pub features: Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>,
+  pub layers: layers::T,
   pub seed: Seed,
 }
 
@@ -30,6 +33,14 @@ pub fn new(seed: Seed) -> T {
       .persistence(8.0)
       .lacunarity(1.0 / 4.0)
     ,
+    layers:
+      layers::T {
+        topsoil_depth: 0.5,
+        dirt_depth: 2.0,
+        max_topsoil_slope: 0.8,
+        sand_line: None,
+        snow_line: Some(64.0),
+      },
   }
 }
 
@@ -74,9 +85,11 @@ impl voxel::field::T for T {
 
 impl voxel::mosaic::T<voxel::Material> for T {
   fn material(&self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let depth = voxel::field::T::density(self, p);
     Some(
-      if voxel::field::T::density(self, p) >= 0.0 {
-        voxel::Material::Stone
+      if depth >= 0.0 {
+        let normal = voxel::field::T::normal(self, p);
+        self.layers.material(&self.seed, p, depth, &normal)
       } else {
         voxel::Material::Empty
       }
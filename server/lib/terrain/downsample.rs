@@ -0,0 +1,125 @@
+//! Rebuild coarse voxels from the finer voxels beneath them.
+
+use cgmath::{Point, Point3, Vector, Vector3};
+
+use common::voxel;
+
+use voxels;
+
+/// A mosaic that reads its density, normals and materials from stored voxels of one size,
+/// falling back to the original mosaic where those voxels haven't been generated.
+pub struct T<'a> {
+  pub voxels: &'a voxels::T,
+  pub mosaic: &'a Box<voxel::mosaic::T<voxel::Material> + Sync>,
+  /// The size of the voxels to sample.
+  pub lg_size: i16,
+}
+
+impl<'a> T<'a> {
+  fn voxel_at(&self, p: &Point3<f32>) -> (voxel::bounds::T, Option<voxel::T>) {
+    let size = (1 << self.lg_size) as f32;
+    let bounds =
+      voxel::bounds::new(
+        (p.x / size).floor() as i32,
+        (p.y / size).floor() as i32,
+        (p.z / size).floor() as i32,
+        self.lg_size,
+      );
+    (bounds, self.voxels.get(&bounds))
+  }
+}
+
+impl<'a> voxel::field::T for T<'a> {
+  fn density(&self, p: &Point3<f32>) -> f32 {
+    match self.voxel_at(p) {
+      (_, None) => voxel::field::T::density(&**self.mosaic, p),
+      (_, Some(voxel::Volume(material))) => {
+        let size = (1 << self.lg_size) as f32;
+        if material == voxel::Material::Empty {
+          -size
+        } else {
+          size
+        }
+      },
+      (bounds, Some(voxel::Surface(voxel))) => {
+        // Approximate the surface within the voxel as a plane.
+        let vertex = voxel.surface_vertex.to_world_vertex(&bounds);
+        let normal = voxel.normal.to_float_normal();
+        // Normals point out of the volume.
+        normal.dot(&vertex.sub_p(p))
+      },
+    }
+  }
+
+  fn normal(&self, p: &Point3<f32>) -> Vector3<f32> {
+    match self.voxel_at(p) {
+      (_, Some(voxel::Surface(voxel))) => voxel.normal.to_float_normal(),
+      _ => voxel::field::T::normal(&**self.mosaic, p),
+    }
+  }
+}
+
+impl<'a> voxel::mosaic::T<voxel::Material> for T<'a> {
+  fn material(&self, p: &Point3<f32>) -> Option<voxel::Material> {
+    if voxel::field::T::density(self, p) < 0.0 {
+      return Some(voxel::Material::Empty)
+    }
+
+    let corner =
+      match self.voxel_at(p) {
+        (_, Some(voxel::Volume(material))) => material,
+        (_, Some(voxel::Surface(voxel))) => voxel.corner,
+        (_, None) => voxel::Material::Empty,
+      };
+
+    if corner != voxel::Material::Empty {
+      return Some(corner)
+    }
+
+    // We're inside the volume, but the nearest stored material is empty;
+    // use whatever was originally generated here instead.
+    match voxel::mosaic::T::material(&**self.mosaic, p) {
+      Some(voxel::Material::Empty) | None => Some(voxel::Material::Terrain),
+      material => material,
+    }
+  }
+}
+
+#[test]
+fn coarse_voxels_match_brushed_voxels() {
+  use cgmath::{Aabb3, Point3, Vector3};
+
+  let terrain = ::T::new(::Seed::new(0));
+  let fill = |low: i32, material| {
+    let center = (low + 16) as f32;
+    let brush =
+      voxel::brush::T {
+        bounds: Aabb3::new(Point3::new(low, low, low), Point3::new(low + 32, low + 32, low + 32)),
+        mosaic: voxel::mosaic::solid::T {
+          field: voxel::field::translation::T {
+            translation: Vector3::new(center, center, center),
+            field: voxel::field::sphere::T { radius: 64.0 },
+          },
+          material: material,
+        },
+        min_lg_size: 0,
+      };
+    terrain.brush(&brush, |_, _, _| {});
+  };
+
+  fill(-8, voxel::Material::Stone);
+  fill(64, voxel::Material::Empty);
+
+  for lg_size in 1 .. ::COARSEST_LG_SIZE + 1 {
+    let is_volume = |x: i32, material| {
+      match terrain.get(&voxel::bounds::new(x >> lg_size, x >> lg_size, x >> lg_size, lg_size)) {
+        Some(voxel::Volume(m)) => m == material,
+        _ => false,
+      }
+    };
+    assert!(is_volume(0, voxel::Material::Stone));
+    assert!(is_volume(8, voxel::Material::Stone));
+    assert!(is_volume(72, voxel::Material::Empty));
+    assert!(is_volume(80, voxel::Material::Empty));
+  }
+}
@@ -23,25 +23,33 @@ extern crate noise;
 extern crate rand;
 extern crate stopwatch;
 extern crate test;
+#[cfg(test)]
+extern crate thread_scoped;
 extern crate time;
 extern crate voxel_data;
 extern crate num;
 
 pub mod biome;
 pub mod tree;
+pub mod voxels;
+
+mod downsample;
 
 pub use noise::Seed;
 
 use cgmath::Aabb;
-use std::sync::Mutex;
+use std::collections::HashSet;
 
 use common::voxel;
 
+/// The coarsest voxels that clients render. Voxels up to this size are kept up to date by brushes.
+pub const COARSEST_LG_SIZE: i16 = 3;
+
 /// This struct contains and lazily generates the world's terrain.
 #[allow(missing_docs)]
 pub struct T {
   pub mosaic: Box<voxel::mosaic::T<voxel::Material> + Sync>,
-  pub voxels: Mutex<voxel::tree::T>,
+  pub voxels: voxels::T,
 }
 
 impl T {
@@ -49,55 +57,242 @@ impl T {
   pub fn new(terrain_seed: Seed) -> T {
     T {
       mosaic: Box::new(biome::hills::new(terrain_seed)),
-      voxels: Mutex::new(voxel::tree::new()),
+      voxels: voxels::new(),
     }
   }
 
-  /// Load the block of terrain at a given position.
-  // TODO: Allow this to be performed in such a way that self is only briefly locked.
-  pub fn load<F>(
-    &self,
-    bounds: &voxel::bounds::T,
-    mut f: F
-  ) where
-    F: FnMut(&voxel::T)
-  {
-    let mut voxels = self.voxels.lock().unwrap();
-    let branches = voxels.get_mut_or_create(bounds);
-    let branches = branches.force_branches();
-    match branches.data {
-      None => {
-        let voxel = voxel::unwrap(voxel::of_field(&self.mosaic, bounds));
-        f(&voxel);
-        branches.data = Some(voxel);
-      },
-      Some(ref data) => {
-        f(data);
-      },
-    }
+  /// Get the voxel at `bounds`, if it has already been generated.
+  pub fn get(&self, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+    self.voxels.get(bounds)
+  }
+
+  /// Generate the voxel at `bounds` from the world's mosaic.
+  /// This doesn't touch the voxel tree, so it can be run from several threads at once.
+  pub fn generate(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    voxel::unwrap(voxel::of_field(&self.mosaic, bounds))
+  }
+
+  /// Store a generated voxel, unless one is already present (e.g. because a brush got there first).
+  /// Returns the voxel that ends up in the tree.
+  pub fn insert(&self, bounds: &voxel::bounds::T, voxel: voxel::T) -> voxel::T {
+    self.voxels.insert(bounds, voxel)
   }
 
   /// Apply a voxel brush to the terrain.
+  /// Any coarser voxels above the changed ones are rebuilt from the changed data,
+  /// and `voxel_changed` is called for those too.
+  /// `voxel_changed` is also passed what the finest voxels the brush wrote were before.
   pub fn brush<VoxelChanged, Mosaic>(
     &self,
     brush: &voxel::brush::T<Mosaic>,
     mut voxel_changed: VoxelChanged,
   ) where
-    VoxelChanged: FnMut(&voxel::T, &voxel::bounds::T),
+    VoxelChanged: FnMut(&voxel::T, Option<&voxel::T>, &voxel::bounds::T),
     Mosaic: voxel::mosaic::T<voxel::Material>,
   {
-    let mut voxels = self.voxels.lock().unwrap();
-    voxels.brush(
+    let mut changed = HashSet::new();
+    self.voxels.brush(
       brush,
       // TODO: Put a max size on this
       &mut |bounds| {
-        if bounds.lg_size > 3 {
+        if bounds.lg_size > COARSEST_LG_SIZE {
           None
         } else {
           Some(voxel::unwrap(voxel::of_field(&self.mosaic, bounds)))
         }
       },
-      &mut voxel_changed,
+      &mut |voxel, previous, bounds| {
+        changed.insert(*bounds);
+        voxel_changed(voxel, previous, bounds);
+      },
     );
+
+    self.downsample(changed, &mut |voxel, bounds| voxel_changed(voxel, None, bounds));
+  }
+
+  /// Overwrite voxels, e.g. to put back what a brush changed.
+  /// Any coarser voxels above them are rebuilt, and `voxel_changed` is called for all of them.
+  /// For the overwritten voxels, `voxel_changed` is also passed what they were before, if they
+  /// were stored.
+  pub fn restore<VoxelChanged>(
+    &self,
+    voxels: &[(voxel::bounds::T, voxel::T)],
+    mut voxel_changed: VoxelChanged,
+  ) where
+    VoxelChanged: FnMut(&voxel::T, Option<&voxel::T>, &voxel::bounds::T),
+  {
+    let mut changed = HashSet::new();
+    for &(ref bounds, ref voxel) in voxels {
+      let previous = self.voxels.get(bounds);
+      self.voxels.set(bounds, *voxel);
+      changed.insert(*bounds);
+      voxel_changed(voxel, previous.as_ref(), bounds);
+    }
+
+    self.downsample(changed, &mut |voxel, bounds| voxel_changed(voxel, None, bounds));
+  }
+
+  /// Rebuild the coarse voxels above a set of changed voxels, one level at a time.
+  fn downsample<VoxelChanged>(
+    &self,
+    mut changed: HashSet<voxel::bounds::T>,
+    voxel_changed: &mut VoxelChanged,
+  ) where
+    VoxelChanged: FnMut(&voxel::T, &voxel::bounds::T),
+  {
+    let finest = match changed.iter().map(|bounds| bounds.lg_size).min() {
+      None => return,
+      Some(lg_size) => lg_size,
+    };
+
+    for lg_size in finest + 1 .. COARSEST_LG_SIZE + 1 {
+      let parents: HashSet<voxel::bounds::T> =
+        changed.iter()
+        .filter(|bounds| bounds.lg_size == lg_size - 1)
+        .map(|bounds| voxel::bounds::new(bounds.x >> 1, bounds.y >> 1, bounds.z >> 1, lg_size))
+        .collect();
+
+      let field =
+        downsample::T {
+          voxels: &self.voxels,
+          mosaic: &self.mosaic,
+          lg_size: lg_size - 1,
+        };
+      for bounds in &parents {
+        let voxel = voxel::unwrap(voxel::of_field(&field, bounds));
+        self.voxels.set(bounds, voxel);
+        voxel_changed(&voxel, bounds);
+      }
+
+      changed.extend(parents.into_iter());
+    }
+  }
+}
+
+#[test]
+fn concurrent_loads_and_brushes() {
+  use cgmath::{Aabb3, Point3, Vector3};
+
+  let terrain = T::new(Seed::new(0));
+  let terrain = &terrain;
+
+  fn loaded(i: i32) -> Vec<voxel::bounds::T> {
+    let mut bounds = Vec::new();
+    for x in -16 .. 16 {
+    for y in -4 .. 4 {
+    for z in -4 .. 4 {
+      bounds.push(voxel::bounds::new(x + 16 * i, y, z, 0));
+    }}}
+    bounds
+  }
+
+  let mut threads = Vec::new();
+  for i in 0 .. 4 {
+    threads.push(unsafe {
+      thread_scoped::scoped(move || {
+        for bounds in loaded(i) {
+          let voxel = terrain.get(&bounds).unwrap_or_else(|| terrain.generate(&bounds));
+          terrain.insert(&bounds, voxel);
+        }
+      })
+    });
+  }
+  for i in 0 .. 4 {
+    threads.push(unsafe {
+      thread_scoped::scoped(move || {
+        for j in 0 .. 8 {
+          // Spread the brushes across region boundaries.
+          let center = Vector3::new((16 * i + 4 * j) as f32, 0.0, 0.0);
+          let r = 4.0;
+          let brush =
+            voxel::brush::T {
+              bounds:
+                Aabb3::new(
+                  Point3::new(center.x as i32 - 5, -5, -5),
+                  Point3::new(center.x as i32 + 5, 5, 5),
+                ),
+              mosaic:
+                voxel::mosaic::solid::T {
+                  field: voxel::field::translation::T {
+                    translation: center,
+                    field: voxel::field::sphere::T {
+                      radius: r,
+                    },
+                  },
+                  material: voxel::Material::Empty,
+                },
+              min_lg_size: 0,
+            };
+          terrain.brush(&brush, |_, _, _| {});
+        }
+      })
+    });
+  }
+
+  for thread in threads.into_iter() {
+    thread.join();
+  }
+
+  for i in 0 .. 4 {
+    for bounds in loaded(i) {
+      assert!(terrain.get(&bounds).is_some(), "{:?} went missing", bounds);
+    }
+  }
+}
+
+#[test]
+fn brushes_pass_on_what_they_overwrote() {
+  use cgmath::{Aabb3, Point3, Vector3};
+
+  let terrain = T::new(Seed::new(0));
+  for x in 0 .. 4 {
+  for y in 0 .. 4 {
+  for z in 0 .. 4 {
+    terrain.insert(&voxel::bounds::new(x, y, z, 0), voxel::Volume(voxel::Material::Stone));
+  }}}
+
+  // Empty out a box that starts half stored (as stone) and half not.
+  let brush =
+    voxel::brush::T {
+      bounds: Aabb3::new(Point3::new(0, 0, 0), Point3::new(4, 4, 8)),
+      mosaic: voxel::mosaic::solid::T {
+        field: voxel::field::translation::T {
+          translation: Vector3::new(2.0, 2.0, 4.0),
+          field: voxel::field::sphere::T { radius: 16.0 },
+        },
+        material: voxel::Material::Empty,
+      },
+      min_lg_size: 0,
+    };
+  let mut overwritten = Vec::new();
+  terrain.brush(&brush, |_, previous, bounds| {
+    if bounds.lg_size == 0 {
+      overwritten.push((*bounds, *previous.expect("the finest voxels should come with what they were")));
+    } else {
+      assert!(previous.is_none());
+    }
+  });
+  let stone = overwritten.iter().filter(|&&(_, voxel)| {
+    match voxel {
+      voxel::Volume(voxel::Material::Stone) => true,
+      _ => false,
+    }
+  });
+  assert_eq!(stone.count(), 64);
+
+  // Putting them back passes on the brushed voxels in turn.
+  let mut replaced = 0;
+  terrain.restore(&overwritten, |_, previous, bounds| {
+    if bounds.lg_size == 0 {
+      match previous {
+        Some(&voxel::Volume(voxel::Material::Empty)) => replaced += 1,
+        _ => panic!("{:?} wasn't emptied", bounds),
+      }
+    }
+  });
+  assert_eq!(replaced, overwritten.len());
+  match terrain.get(&voxel::bounds::new(1, 2, 3, 0)) {
+    Some(voxel::Volume(voxel::Material::Stone)) => {},
+    _ => panic!("stone wasn't put back"),
   }
 }
@@ -0,0 +1,502 @@
+//! Voxel storage, sharded into fixed-size regions of the world so that work on
+//! different parts of the world doesn't all contend on one lock.
+//! Regions that haven't been edited can be evicted to save memory, since they can always be
+//! regenerated from the mosaic.
+
+use cgmath::{Aabb3, Point3, Ray3, Vector3};
+use std::cell::RefCell;
+use std::cmp::{min, max};
+use std::collections::{HashMap, HashSet};
+use std::f32;
+use std::mem;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use common::voxel;
+
+/// lg of the width of a region, in world units.
+pub const LG_REGION_WIDTH: i16 = 5;
+
+/// A rough estimate of the memory used to store each voxel.
+fn bytes_per_voxel() -> usize {
+  mem::size_of::<voxel::tree::Branches>()
+}
+
+struct Region {
+  tree: RwLock<voxel::tree::T>,
+  /// Whether a brush has changed this region. Edited regions can't be regenerated, so they're never evicted.
+  edited: AtomicBool,
+  /// When this region was last accessed, by the clock of the `T` that holds it.
+  last_access: AtomicUsize,
+  /// The estimated number of bytes used by this region's voxels.
+  bytes: AtomicUsize,
+}
+
+impl Region {
+  fn new() -> Region {
+    Region {
+      tree: RwLock::new(voxel::tree::new()),
+      edited: AtomicBool::new(false),
+      last_access: AtomicUsize::new(0),
+      bytes: AtomicUsize::new(0),
+    }
+  }
+
+  fn touch(&self, now: usize) {
+    self.last_access.store(now, Ordering::Relaxed);
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// This is only write-locked to add or evict regions.
+  regions: RwLock<HashMap<Point3<i32>, Arc<Region>>>,
+  /// Counts region accesses, to order them for eviction.
+  clock: AtomicUsize,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    regions: RwLock::new(HashMap::new()),
+    clock: AtomicUsize::new(0),
+  }
+}
+
+/// A summary of the memory used by the voxels.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+  /// The number of regions currently resident.
+  pub regions: usize,
+  /// The number of resident regions that contain edits.
+  pub edited_regions: usize,
+  /// The estimated number of bytes used by the voxels.
+  pub bytes: usize,
+}
+
+/// The position of the region a voxel is stored in, in units of regions.
+/// Voxels that span several regions are stored in the region containing their low corner.
+pub fn region_of(bounds: &voxel::bounds::T) -> Point3<i32> {
+  let shift = bounds.lg_size - LG_REGION_WIDTH;
+  let f = |x: i32| if shift >= 0 { x << shift } else { x >> -shift };
+  Point3::new(f(bounds.x), f(bounds.y), f(bounds.z))
+}
+
+/// Borrow a mosaic as a mosaic.
+struct MosaicRef<'a, Mosaic: 'a>(&'a Mosaic);
+
+impl<'a, Mosaic> voxel::field::T for MosaicRef<'a, Mosaic> where
+  Mosaic: voxel::mosaic::T<voxel::Material>,
+{
+  fn density(&self, p: &Point3<f32>) -> f32 {
+    voxel::field::T::density(self.0, p)
+  }
+
+  fn normal(&self, p: &Point3<f32>) -> Vector3<f32> {
+    voxel::field::T::normal(self.0, p)
+  }
+}
+
+impl<'a, Mosaic> voxel::mosaic::T<voxel::Material> for MosaicRef<'a, Mosaic> where
+  Mosaic: voxel::mosaic::T<voxel::Material>,
+{
+  fn material(&self, p: &Point3<f32>) -> Option<voxel::Material> {
+    voxel::mosaic::T::material(self.0, p)
+  }
+}
+
+impl T {
+  fn now(&self) -> usize {
+    self.clock.fetch_add(1, Ordering::Relaxed)
+  }
+
+  fn region(&self, position: &Point3<i32>) -> Option<Arc<Region>> {
+    self.regions.read().unwrap().get(position).map(|region| {
+      region.touch(self.now());
+      region.clone()
+    })
+  }
+
+  /// Get a region, creating it if necessary. If `edit` is set, the region is marked as edited
+  /// before the map of regions is unlocked, so that it can't be evicted out from under us.
+  fn region_or_create(&self, position: &Point3<i32>, edit: bool) -> Arc<Region> {
+    let prepare = |region: &Arc<Region>| {
+      if edit {
+        region.edited.store(true, Ordering::SeqCst);
+      }
+      region.touch(self.now());
+      region.clone()
+    };
+
+    {
+      let regions = self.regions.read().unwrap();
+      if let Some(region) = regions.get(position) {
+        return prepare(region)
+      }
+    }
+
+    let mut regions = self.regions.write().unwrap();
+    let region = regions.entry(*position).or_insert_with(|| Arc::new(Region::new()));
+    prepare(region)
+  }
+
+  /// The estimated number of bytes used by the voxels.
+  pub fn memory_usage(&self) -> usize {
+    self.regions.read().unwrap().values()
+      .map(|region| region.bytes.load(Ordering::Relaxed))
+      .fold(0, |x, y| x + y)
+  }
+
+  #[allow(missing_docs)]
+  pub fn stats(&self) -> Stats {
+    let regions = self.regions.read().unwrap();
+    Stats {
+      regions: regions.len(),
+      edited_regions: regions.values().filter(|r| r.edited.load(Ordering::Relaxed)).count(),
+      bytes: regions.values().map(|r| r.bytes.load(Ordering::Relaxed)).fold(0, |x, y| x + y),
+    }
+  }
+
+  /// Evict the least-recently-accessed unedited regions until the voxels fit in `budget` bytes.
+  /// Regions for which `is_held` returns true are still in use, and are kept.
+  /// Returns the number of regions evicted.
+  pub fn evict<IsHeld>(&self, budget: usize, is_held: IsHeld) -> usize
+    where IsHeld: Fn(&Point3<i32>) -> bool
+  {
+    let mut usage = self.memory_usage();
+    if usage <= budget {
+      return 0
+    }
+
+    let mut regions = self.regions.write().unwrap();
+    let mut candidates: Vec<_> =
+      regions.iter()
+      .filter(|&(position, region)| !region.edited.load(Ordering::SeqCst) && !is_held(position))
+      .map(|(position, region)| (region.last_access.load(Ordering::Relaxed), *position))
+      .collect();
+    candidates.sort_by_key(|&(last_access, _)| last_access);
+
+    let mut evicted = 0;
+    for (_, position) in candidates {
+      if usage <= budget {
+        break
+      }
+      let region = regions.remove(&position).unwrap();
+      usage = usage.saturating_sub(region.bytes.load(Ordering::Relaxed));
+      evicted += 1;
+    }
+
+    evicted
+  }
+
+  /// The smallest box of regions containing every region.
+  fn extent(&self) -> Option<Aabb3<i32>> {
+    let regions = self.regions.read().unwrap();
+    let mut positions = regions.keys();
+    positions.next().map(|first| {
+      let mut low = *first;
+      let mut high = *first;
+      for p in positions {
+        low = Point3::new(min(low.x, p.x), min(low.y, p.y), min(low.z, p.z));
+        high = Point3::new(max(high.x, p.x), max(high.y, p.y), max(high.z, p.z));
+      }
+      Aabb3::new(low, high)
+    })
+  }
+
+  /// Get the voxel at `bounds`, if it's present.
+  pub fn get(&self, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+    self.region(&region_of(bounds)).and_then(|region| {
+      let voxel = region.tree.read().unwrap().get(bounds).cloned();
+      voxel
+    })
+  }
+
+  /// Store a voxel, unless one is already present. Returns the voxel that ends up stored.
+  pub fn insert(&self, bounds: &voxel::bounds::T, voxel: voxel::T) -> voxel::T {
+    let region = self.region_or_create(&region_of(bounds), false);
+    let mut tree = region.tree.write().unwrap();
+    let branches = tree.get_mut_or_create(bounds);
+    let branches = branches.force_branches();
+    match branches.data {
+      None => {
+        branches.data = Some(voxel);
+        region.bytes.fetch_add(bytes_per_voxel(), Ordering::Relaxed);
+        voxel
+      },
+      Some(data) => data,
+    }
+  }
+
+  /// Store a voxel derived from edits, replacing whatever was there.
+  pub fn set(&self, bounds: &voxel::bounds::T, voxel: voxel::T) {
+    let region = self.region_or_create(&region_of(bounds), true);
+    let mut tree = region.tree.write().unwrap();
+    let branches = tree.get_mut_or_create(bounds);
+    let branches = branches.force_branches();
+    if branches.data.is_none() {
+      region.bytes.fetch_add(bytes_per_voxel(), Ordering::Relaxed);
+    }
+    branches.data = Some(voxel);
+  }
+
+  /// Apply a brush, one region at a time.
+  /// `voxel_changed` is passed each changed voxel, and what it was before if it's one of the finest
+  /// voxels the brush writes (at its `min_lg_size`).
+  pub fn brush<Mosaic, Generate, VoxelChanged>(
+    &self,
+    brush: &voxel::brush::T<Mosaic>,
+    generate: &mut Generate,
+    voxel_changed: &mut VoxelChanged,
+  ) where
+    Mosaic: voxel::mosaic::T<voxel::Material>,
+    Generate: FnMut(&voxel::bounds::T) -> Option<voxel::T>,
+    VoxelChanged: FnMut(&voxel::T, Option<&voxel::T>, &voxel::bounds::T),
+  {
+    let lg = LG_REGION_WIDTH;
+    let finest = brush.min_lg_size;
+    let low = brush.bounds.min;
+    let high = brush.bounds.max;
+    for x in (low.x >> lg) .. ((high.x - 1) >> lg) + 1 {
+    for y in (low.y >> lg) .. ((high.y - 1) >> lg) + 1 {
+    for z in (low.z >> lg) .. ((high.z - 1) >> lg) + 1 {
+      let region_low = Point3::new(x << lg, y << lg, z << lg);
+      let region_high = Point3::new((x + 1) << lg, (y + 1) << lg, (z + 1) << lg);
+      let region_brush =
+        voxel::brush::T {
+          bounds:
+            Aabb3::new(
+              Point3::new(max(low.x, region_low.x), max(low.y, region_low.y), max(low.z, region_low.z)),
+              Point3::new(min(high.x, region_high.x), min(high.y, region_high.y), min(high.z, region_high.z)),
+            ),
+          mosaic: MosaicRef(&brush.mosaic),
+          min_lg_size: brush.min_lg_size,
+        };
+
+      let region = self.region_or_create(&Point3::new(x, y, z), true);
+      let mut tree = region.tree.write().unwrap();
+
+      // Remember which voxels were stored, so only new ones add to the region's size, and what
+      // the finest ones were. Finest ones that weren't stored are remembered as they're generated
+      // for the brush.
+      let mut stored = HashSet::new();
+      let mut previous = HashMap::new();
+      {
+        let (low, high) = (region_brush.bounds.min, region_brush.bounds.max);
+        for lg_size in finest .. lg + 1 {
+          for vx in (low.x >> lg_size) .. ((high.x - 1) >> lg_size) + 1 {
+          for vy in (low.y >> lg_size) .. ((high.y - 1) >> lg_size) + 1 {
+          for vz in (low.z >> lg_size) .. ((high.z - 1) >> lg_size) + 1 {
+            let bounds = voxel::bounds::new(vx, vy, vz, lg_size);
+            if let Some(voxel) = tree.get(&bounds) {
+              stored.insert(bounds);
+              if lg_size == finest {
+                previous.insert(bounds, *voxel);
+              }
+            }
+          }}}
+        }
+      }
+      let previous = RefCell::new(previous);
+
+      let mut added = 0;
+      tree.brush(
+        &region_brush,
+        &mut |bounds| {
+          let voxel = generate(bounds);
+          if let Some(voxel) = voxel {
+            if bounds.lg_size == finest {
+              previous.borrow_mut().entry(*bounds).or_insert(voxel);
+            }
+          }
+          voxel
+        },
+        &mut |voxel, bounds| {
+          if stored.insert(*bounds) {
+            added += 1;
+          }
+          let previous = previous.borrow().get(bounds).cloned();
+          voxel_changed(voxel, previous.as_ref(), bounds);
+        },
+      );
+      region.bytes.fetch_add(added * bytes_per_voxel(), Ordering::Relaxed);
+    }}}
+  }
+
+  /// Cast a ray through the voxels, and return the first non-`None` result of `f`.
+  /// Regions are visited in the order the ray passes through them.
+  pub fn cast_ray<R, F>(
+    &self,
+    ray: &Ray3<f32>,
+    f: &mut F,
+  ) -> Option<R> where
+    F: FnMut(voxel::bounds::T, &voxel::T) -> Option<R>,
+  {
+    let extent =
+      match self.extent() {
+        None => return None,
+        Some(extent) => extent,
+      };
+
+    for position in regions_along(ray, &extent) {
+      let region =
+        match self.region(&position) {
+          None => continue,
+          Some(region) => region,
+        };
+      let r = region.tree.read().unwrap().cast_ray(ray, &mut |bounds, voxel| f(bounds, voxel));
+      if r.is_some() {
+        return r
+      }
+    }
+
+    None
+  }
+}
+
+/// The region positions a ray passes through while inside `extent`, in order.
+pub fn regions_along(ray: &Ray3<f32>, extent: &Aabb3<i32>) -> Vec<Point3<i32>> {
+  let width = (1 << LG_REGION_WIDTH) as f32;
+
+  let cell = |x: f32| (x / width).floor() as i32;
+  let step = |d: f32| if d > 0.0 { 1 } else if d < 0.0 { -1 } else { 0 };
+  // The distance along the ray to the first region boundary along one axis.
+  let first_boundary = |o: f32, d: f32, c: i32| {
+    if d > 0.0 {
+      ((c + 1) as f32 * width - o) / d
+    } else if d < 0.0 {
+      (c as f32 * width - o) / d
+    } else {
+      f32::INFINITY
+    }
+  };
+  // The distance along the ray between region boundaries along one axis.
+  let boundary_delta = |d: f32| if d == 0.0 { f32::INFINITY } else { width / d.abs() };
+
+  let o = ray.origin;
+  let d = ray.direction;
+
+  let mut position = Point3::new(cell(o.x), cell(o.y), cell(o.z));
+  let step = Vector3::new(step(d.x), step(d.y), step(d.z));
+  let mut next =
+    Vector3::new(
+      first_boundary(o.x, d.x, position.x),
+      first_boundary(o.y, d.y, position.y),
+      first_boundary(o.z, d.z, position.z),
+    );
+  let delta = Vector3::new(boundary_delta(d.x), boundary_delta(d.y), boundary_delta(d.z));
+
+  // Whether the ray can never come back into `extent` along one axis.
+  let is_past = |p: i32, step: i32, low: i32, high: i32| {
+    if step > 0 {
+      p > high
+    } else if step < 0 {
+      p < low
+    } else {
+      p < low || p > high
+    }
+  };
+
+  let mut positions = Vec::new();
+  loop {
+    if is_past(position.x, step.x, extent.min.x, extent.max.x)
+    || is_past(position.y, step.y, extent.min.y, extent.max.y)
+    || is_past(position.z, step.z, extent.min.z, extent.max.z) {
+      break
+    }
+
+    let inside =
+      extent.min.x <= position.x && position.x <= extent.max.x &&
+      extent.min.y <= position.y && position.y <= extent.max.y &&
+      extent.min.z <= position.z && position.z <= extent.max.z;
+    if inside {
+      positions.push(position);
+    }
+
+    if step.x == 0 && step.y == 0 && step.z == 0 {
+      break
+    }
+
+    if next.x <= next.y && next.x <= next.z {
+      position.x += step.x;
+      next.x += delta.x;
+    } else if next.y <= next.z {
+      position.y += step.y;
+      next.y += delta.y;
+    } else {
+      position.z += step.z;
+      next.z += delta.z;
+    }
+  }
+
+  positions
+}
+
+#[test]
+fn ray_visits_regions_in_order() {
+  use cgmath::Ray;
+
+  let width = (1 << LG_REGION_WIDTH) as f32;
+  let ray = Ray::new(Point3::new(width / 2.0, width / 2.0, width / 2.0), Vector3::new(1.0, 0.0, 0.0));
+  let extent = Aabb3::new(Point3::new(-2, -2, -2), Point3::new(2, 2, 2));
+  assert_eq!(
+    regions_along(&ray, &extent),
+    vec!(Point3::new(0, 0, 0), Point3::new(1, 0, 0), Point3::new(2, 0, 0)),
+  );
+}
+
+#[test]
+fn evicts_least_recently_touched_unedited_regions() {
+  let voxels = new();
+  let width = 1 << LG_REGION_WIDTH;
+  let voxel = voxel::Volume(voxel::Material::Stone);
+  let in_region = |i: i32| voxel::bounds::new(i * width, 0, 0, 0);
+
+  voxels.set(&in_region(0), voxel);
+  for i in 1 .. 4 {
+    voxels.insert(&in_region(i), voxel);
+  }
+  // Touch the oldest unedited region, so the next two go first.
+  assert!(voxels.get(&in_region(1)).is_some());
+
+  let budget = 2 * bytes_per_voxel();
+  assert_eq!(voxels.evict(budget, |_| false), 2);
+
+  let stats = voxels.stats();
+  assert!(stats.bytes <= budget);
+  assert_eq!(stats.regions, 2);
+  assert_eq!(stats.edited_regions, 1);
+  assert!(voxels.get(&in_region(0)).is_some());
+  assert!(voxels.get(&in_region(1)).is_some());
+  assert!(voxels.get(&in_region(2)).is_none());
+  assert!(voxels.get(&in_region(3)).is_none());
+
+  // Regions that are still held aren't evicted, however far over budget we are.
+  assert_eq!(voxels.evict(0, |position| position.x == 1), 0);
+  assert!(voxels.get(&in_region(1)).is_some());
+}
+
+#[test]
+fn brushing_over_stored_voxels_takes_no_more_memory() {
+  let voxels = new();
+  let brush = |material| {
+    voxel::brush::T {
+      bounds: Aabb3::new(Point3::new(0, 0, 0), Point3::new(4, 4, 4)),
+      mosaic: voxel::mosaic::solid::T {
+        field: voxel::field::translation::T {
+          translation: Vector3::new(2.0, 2.0, 2.0),
+          field: voxel::field::sphere::T { radius: 16.0 },
+        },
+        material: material,
+      },
+      min_lg_size: 0,
+    }
+  };
+  let mut generate = |_: &voxel::bounds::T| Some(voxel::Volume(voxel::Material::Stone));
+
+  voxels.brush(&brush(voxel::Material::Empty), &mut generate, &mut |_, _, _| {});
+  let bytes = voxels.memory_usage();
+  assert!(bytes > 0);
+  voxels.brush(&brush(voxel::Material::Dirt), &mut generate, &mut |_, _, _| {});
+  assert_eq!(voxels.memory_usage(), bytes);
+}
@@ -29,11 +29,13 @@ fn main() {
   let server_url = String::from("ipc:///tmp/server.ipc");
 
   let quit_signal = Mutex::new(false);
+  // There's no console in singleplayer.
+  let admin = server_lib::admin::inbox();
 
   unsafe {
     let _server_thread =
       thread_scoped::scoped(|| {
-        server_lib::run(server_url.borrow(), &quit_signal);
+        server_lib::run(server_url.borrow(), &quit_signal, &admin, Default::default());
       });
 
     client_lib::run(listen_url.borrow(), server_url.borrow());